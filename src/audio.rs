@@ -1,60 +1,182 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, Ordering };
+
 use sdl2;
 use sdl2::audio::{ AudioDevice, AudioCallback, AudioSpecDesired };
 
+use crate::beeper::{ self, Beeper, Consumer, Producer };
+use crate::capture::Capture;
+use crate::config::Config;
+use crate::movie::SoundEvent;
+use crate::timing::FRAME_HZ;
+use crate::tone::{ Tone, SAMPLE_RATE };
+
 // Acts as the callback that AudioDevice uses to play sounds
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume:f32,
+struct Callback {
+    beeper: Beeper,
 }
 
 pub struct Audio {
-    device: AudioDevice<SquareWave>,
+    // None when sound is off or there's no device to play it on
+    device: Option<AudioDevice<Callback>>,
+    events: Producer,
+    // Samples produced so far
+    position: Arc<AtomicU64>,
+    // How far ahead of the device events are scheduled, in samples.
+    // A frame's events are spread over the frame after it, so they're
+    // never due before they've been queued
+    latency: u64,
+    frame_samples: f64,
+    // Sample of the last event queued, events never go back in time
+    last_event: u64,
+    muted: bool,
+    paused: bool,
+    // The WAV file, which follows emulated time rather than the device
+    capture: Option<Capture>,
 }
 
-impl AudioCallback for SquareWave {
+impl AudioCallback for Callback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.beeper.fill(out);
     }
 }
 
 impl Audio {
-    pub fn new(ctx: &sdl2::Sdl) -> Audio {
-        let audio_subsystem = ctx.audio().unwrap();
+    // Runs silently when sound is turned off or there's no device to play it on,
+    // the WAV file is written either way
+    pub fn new(ctx: &sdl2::Sdl, config: &Config) -> Audio {
+        let (producer, consumer) = beeper::channel();
 
-        let desired_spec = AudioSpecDesired {
-            freq: Some(44100),
-            channels: Some(1),
-            samples: None,
+        let mut device = if config.audio {
+            match open_device(ctx, config, consumer) {
+                Ok(device) => Some(device),
+                Err(e) => {
+                    eprintln!("chip8: no audio device, sound is off: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
         };
 
-        let device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25
+        let capture = config.wav.as_ref().and_then(|path| {
+            Capture::create(path, config)
+                .map_err(|e| eprintln!("chip8: could not record audio to {}: {}", path, e))
+                .ok()
+        });
+
+        let (position, latency, sample_rate) = match device {
+            Some(ref mut device) => {
+                let (freq, buffer_samples) = (device.spec().freq, device.spec().samples);
+                let latency = buffer_samples as u64 + (freq as f64 / FRAME_HZ) as u64;
+
+                (device.lock().beeper.position(), latency, freq as u32)
             }
-        }).unwrap();
+            None => (Arc::new(AtomicU64::new(0)), 0, SAMPLE_RATE),
+        };
 
-        Audio {
-            device: device,
+        // The device always runs, the tone fades in and out on its own
+        if let Some(ref device) = device {
+            device.resume();
         }
+
+        let mut audio = Audio {
+            device,
+            events: producer,
+            position,
+            latency,
+            frame_samples: sample_rate as f64 / FRAME_HZ,
+            last_event: 0,
+            muted: config.mute,
+            paused: false,
+            capture,
+        };
+
+        audio.update_silenced();
+        audio
     }
 
-    pub fn play(&self) {
-        self.device.resume();
+    // Schedules the sound changes from one pass of the main loop,
+    // which are spread over the next frame of audio.
+    // ticks is how many timer ticks of emulated time the pass ran
+    pub fn queue(&mut self, events: &[SoundEvent], ticks: u32) {
+        // Like video, a capture only has the time the game was running
+        if let Some(Err(e)) = self.capture.as_mut().map(|c| c.run(events, ticks)) {
+            eprintln!("chip8: error writing audio, capture stopped: {}", e);
+            self.capture = None;
+        }
+
+        if self.device.is_none() {
+            return;
+        }
+
+        let start = self.position.load(Ordering::Relaxed) + self.latency;
+
+        for event in events {
+            let sample = (start + (event.at * self.frame_samples) as u64).max(self.last_event);
+            self.last_event = sample;
+
+            if !self.events.push(sample, event.on) {
+                eprintln!("chip8: sound queue full, dropping events");
+            }
+        }
     }
 
-    pub fn stop(&self) {
-        self.device.pause();
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused != self.paused {
+            self.paused = paused;
+            self.update_silenced();
+        }
     }
+
+    // Returns whether sound is now muted
+    pub fn toggle_mute(&mut self) -> bool {
+        self.muted = !self.muted;
+        self.update_silenced();
+
+        self.muted
+    }
+
+    // Stops the device and finishes the WAV file, if audio is being captured
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(ref device) = self.device {
+            device.pause();
+        }
+
+        match self.capture {
+            Some(c) => c.finish(),
+            None => Ok(()),
+        }
+    }
+
+    fn update_silenced(&mut self) {
+        if let Some(ref mut device) = self.device {
+            device.lock().beeper.set_silenced(self.muted || self.paused);
+        }
+
+        // Paused time isn't captured at all, so only muting matters
+        if let Some(ref mut c) = self.capture {
+            c.set_silenced(self.muted);
+        }
+    }
+}
+
+// Opens the sound device with the callback that plays the queued events
+fn open_device(ctx: &sdl2::Sdl, config: &Config, events: Consumer) -> Result<AudioDevice<Callback>, String> {
+    let audio_subsystem = ctx.audio()?;
+
+    let desired_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: None,
+    };
+
+    audio_subsystem.open_playback(None, &desired_spec, |spec| {
+        let tone = Tone::new(config.waveform, config.tone_hz, config.volume, spec.freq as u32);
+
+        Callback { beeper: Beeper::new(tone, events) }
+    })
 }
@@ -0,0 +1,133 @@
+// Plays sound events at the sample they're due
+//
+// The emulator thread stamps every sound on/off with the output sample it
+// should land on and passes it to the audio callback through a lock-free
+// single producer, single consumer ring. The callback flips the tone at
+// exactly that sample, so short beeps keep their length no matter how the
+// emulator's frames line up with the audio buffers
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+
+use crate::tone::Tone;
+
+// Events that can be waiting at once, any more are dropped
+const QUEUE_SIZE: usize = 1024;
+
+// Events are packed into a single word so a slot is one atomic,
+// the sample index shifted up with the on flag in the low bit
+struct Ring {
+    slots: Vec<AtomicU64>,
+    // Total events ever written and read, the difference is what's waiting
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+pub struct Producer {
+    ring: Arc<Ring>,
+}
+
+pub struct Consumer {
+    ring: Arc<Ring>,
+}
+
+pub fn channel() -> (Producer, Consumer) {
+    let ring = Arc::new(Ring {
+        slots: (0..QUEUE_SIZE).map(|_| AtomicU64::new(0)).collect(),
+        written: AtomicUsize::new(0),
+        read: AtomicUsize::new(0),
+    });
+
+    (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+impl Producer {
+    // Returns false if the queue was full and the event was dropped
+    pub fn push(&self, sample: u64, on: bool) -> bool {
+        let written = self.ring.written.load(Ordering::Relaxed);
+
+        if written - self.ring.read.load(Ordering::Acquire) == QUEUE_SIZE {
+            return false;
+        }
+
+        self.ring.slots[written % QUEUE_SIZE].store(sample << 1 | on as u64, Ordering::Relaxed);
+        self.ring.written.store(written + 1, Ordering::Release);
+
+        true
+    }
+}
+
+impl Consumer {
+    // The oldest waiting event as (sample, on), without taking it
+    pub fn peek(&self) -> Option<(u64, bool)> {
+        let read = self.ring.read.load(Ordering::Relaxed);
+
+        if read == self.ring.written.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let packed = self.ring.slots[read % QUEUE_SIZE].load(Ordering::Relaxed);
+        Some((packed >> 1, packed & 1 != 0))
+    }
+
+    pub fn pop(&self) {
+        let read = self.ring.read.load(Ordering::Relaxed);
+        self.ring.read.store(read + 1, Ordering::Release);
+    }
+}
+
+// The audio callback's side
+pub struct Beeper {
+    tone: Tone,
+    events: Consumer,
+    // Samples produced so far, shared so the emulator can stamp events
+    position: Arc<AtomicU64>,
+    // What the sound timer says
+    sounding: bool,
+    // Muted or paused, the tone stays off whatever the events say
+    silenced: bool,
+}
+
+impl Beeper {
+    pub fn new(tone: Tone, events: Consumer) -> Beeper {
+        Beeper {
+            tone,
+            events,
+            position: Arc::new(AtomicU64::new(0)),
+            sounding: false,
+            silenced: false,
+        }
+    }
+
+    // Shared count of samples produced so far
+    #[cfg(feature = "sdl")]
+    pub fn position(&self) -> Arc<AtomicU64> {
+        self.position.clone()
+    }
+
+    pub fn set_silenced(&mut self, silenced: bool) {
+        self.silenced = silenced;
+        self.tone.set_gate(self.sounding && !silenced);
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let mut position = self.position.load(Ordering::Relaxed);
+
+        for x in out.iter_mut() {
+            // Events that arrived late are applied straight away
+            while let Some((sample, on)) = self.events.peek() {
+                if sample > position {
+                    break;
+                }
+
+                self.events.pop();
+                self.sounding = on;
+                self.tone.set_gate(on && !self.silenced);
+            }
+
+            *x = self.tone.next_sample();
+            position += 1;
+        }
+
+        self.position.store(position, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,262 @@
+// The ROM browser shown when the emulator starts without a ROM
+// Lists the ROMs in a directory with what the ROM database knows about them,
+// and draws itself into a framebuffer like a game would
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use crate::cpu::{ MEMORY_SIZE, PROGRAM_START };
+use crate::font::{ self, ADVANCE, GLYPH_HEIGHT };
+use crate::romdb::RomDb;
+
+// Files with one of these extensions, or none at all, are listed as ROMs
+pub const ROM_EXTS: [&str; 6] = ["ch8", "c8", "sc8", "xo8", "rom", "bin"];
+
+// The browser's framebuffer, 4x the window's default 64x32 scale
+pub const MENU_WIDTH: usize = 160;
+pub const MENU_HEIGHT: usize = 80;
+
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 1;
+const LIST_TOP: usize = 9;
+const VISIBLE: usize = 8;
+const INFO_TOP: usize = LIST_TOP + VISIBLE * LINE_HEIGHT + 2;
+
+// Rows of the list PageUp and PageDown move by
+const PAGE: usize = VISIBLE - 1;
+
+// What the player pressed, whichever device it came from
+#[derive(Clone, Copy, PartialEq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    First,
+    Last,
+    Select,
+    Back,
+}
+
+pub enum Action {
+    // Nothing to do but draw the menu again
+    Redraw,
+    Launch(PathBuf),
+    Quit,
+}
+
+struct Item {
+    path: PathBuf,
+    // The title from the database, or the file name
+    name: String,
+    size: usize,
+    platform: Option<String>,
+    description: Option<String>,
+}
+
+pub struct Browser {
+    pub dir: String,
+    items: Vec<Item>,
+    selected: usize,
+    // First item shown in the list
+    top: usize,
+    // Why the directory couldn't be listed
+    error: Option<String>,
+}
+
+impl Browser {
+    pub fn new(dir: &str) -> Browser {
+        let mut browser = Browser { dir: dir.to_string(), items: Vec::new(), selected: 0, top: 0, error: None };
+
+        browser.refresh();
+        browser
+    }
+
+    // Lists the directory again, keeping the same ROM selected if it's still there
+    pub fn refresh(&mut self) {
+        let current = self.items.get(self.selected).map(|item| item.path.clone());
+
+        self.items.clear();
+        self.error = None;
+
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("chip8: could not read {}: {}", self.dir, e);
+                self.error = Some(format!("could not read {}", self.dir));
+                return;
+            }
+        };
+
+        let db = RomDb::load().unwrap_or_else(|e| {
+            eprintln!("chip8: {}", e);
+            RomDb::empty()
+        });
+
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| is_rom(path))
+            .collect();
+
+        paths.sort();
+
+        for path in paths {
+            // Too big to load anyway, and not worth reading a large file to find out
+            let fits = fs::metadata(&path).is_ok_and(|m| m.len() <= (MEMORY_SIZE - PROGRAM_START) as u64);
+
+            if !fits {
+                continue;
+            }
+
+            let bytes = match fs::read(&path) {
+                Ok(bytes) => bytes,
+                Err(_) => continue,
+            };
+
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let entry = db.lookup(&bytes);
+
+            self.items.push(Item {
+                name: entry.and_then(|e| e.title.clone()).unwrap_or(file_name),
+                size: bytes.len(),
+                platform: entry.and_then(|e| e.platform.clone()),
+                description: entry.and_then(|e| e.description.clone()),
+                path,
+            });
+        }
+
+        self.selected = current
+            .and_then(|path| self.items.iter().position(|item| item.path == path))
+            .unwrap_or(0);
+        self.top = 0;
+        self.scroll();
+    }
+
+    pub fn handle(&mut self, key: MenuKey) -> Action {
+        let last = self.items.len().saturating_sub(1);
+
+        self.selected = match key {
+            MenuKey::Up => self.selected.saturating_sub(1),
+            MenuKey::Down => (self.selected + 1).min(last),
+            MenuKey::PageUp => self.selected.saturating_sub(PAGE),
+            MenuKey::PageDown => (self.selected + PAGE).min(last),
+            MenuKey::First => 0,
+            MenuKey::Last => last,
+            MenuKey::Select => {
+                return match self.items.get(self.selected) {
+                    Some(item) => Action::Launch(item.path.clone()),
+                    None => Action::Redraw,
+                };
+            }
+            MenuKey::Back => return Action::Quit,
+        };
+
+        self.scroll();
+        Action::Redraw
+    }
+
+    // Keeps the selected item in view
+    fn scroll(&mut self) {
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + VISIBLE {
+            self.top = self.selected + 1 - VISIBLE;
+        }
+    }
+
+    // MENU_WIDTH x MENU_HEIGHT pixels, one byte each
+    pub fn render(&self) -> Vec<u8> {
+        let mut pixels = vec![0; MENU_WIDTH * MENU_HEIGHT];
+        let columns = font::columns(MENU_WIDTH);
+
+        // Directory on the left, position on the right
+        let position = format!("{}/{}", (self.selected + 1).min(self.items.len()), self.items.len());
+        font::draw_text(&mut pixels, MENU_WIDTH, 1, 1, &fit(&self.dir, columns - position.len() - 1), 1);
+        font::draw_text(&mut pixels, MENU_WIDTH, MENU_WIDTH - position.len() * ADVANCE, 1, &position, 1);
+        rule(&mut pixels, LIST_TOP - 2);
+
+        if self.items.is_empty() {
+            let message = self.error.clone().unwrap_or_else(|| format!("no ROMs in {}", self.dir));
+            font::draw_text(&mut pixels, MENU_WIDTH, 1, LIST_TOP + 1, &fit(&message, columns), 1);
+            return pixels;
+        }
+
+        for (row, (i, item)) in self.items.iter().enumerate().skip(self.top).take(VISIBLE).enumerate() {
+            let y = LIST_TOP + row * LINE_HEIGHT;
+
+            // The selected row is drawn inverted
+            let ink = if i == self.selected {
+                for pixel in &mut pixels[(y - 1) * MENU_WIDTH..(y + GLYPH_HEIGHT) * MENU_WIDTH] {
+                    *pixel = 1;
+                }
+
+                0
+            } else {
+                1
+            };
+
+            font::draw_text(&mut pixels, MENU_WIDTH, 1, y, &fit(&item.name, columns), ink);
+        }
+
+        rule(&mut pixels, INFO_TOP - 2);
+
+        let item = &self.items[self.selected];
+        let file_name = item.path.file_name().unwrap_or_default().to_string_lossy();
+        let summary = format!("{}  {} bytes  {}", item.platform.as_deref().unwrap_or("unknown"), item.size, file_name);
+        font::draw_text(&mut pixels, MENU_WIDTH, 1, INFO_TOP, &fit(&summary, columns), 1);
+
+        let description = item.description.as_deref().unwrap_or("");
+
+        for (row, line) in wrap(description, columns).iter().take(2).enumerate() {
+            font::draw_text(&mut pixels, MENU_WIDTH, 1, INFO_TOP + (row + 1) * LINE_HEIGHT, line, 1);
+        }
+
+        pixels
+    }
+}
+
+fn is_rom(path: &Path) -> bool {
+    let hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+
+    let rom_ext = match path.extension() {
+        Some(ext) => ROM_EXTS.iter().any(|&e| ext.eq_ignore_ascii_case(e)),
+        None => true,
+    };
+
+    path.is_file() && !hidden && rom_ext
+}
+
+// A horizontal line across the menu
+fn rule(pixels: &mut [u8], y: usize) {
+    for pixel in &mut pixels[y * MENU_WIDTH..(y + 1) * MENU_WIDTH] {
+        *pixel = 1;
+    }
+}
+
+// Cuts text down to a number of characters
+fn fit(text: &str, columns: usize) -> String {
+    text.chars().take(columns).collect()
+}
+
+// Splits text into lines of at most columns characters, between words where it can
+fn wrap(text: &str, columns: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+            lines.push(line);
+            line = String::new();
+        }
+
+        if !line.is_empty() {
+            line.push(' ');
+        }
+
+        line.push_str(&fit(word, columns));
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    lines
+}
@@ -0,0 +1,69 @@
+// Records the beep to a WAV file on emulated time rather than the sound
+// device's. Every timer tick gets one 60hz frame of samples, like the video
+// gets one picture, so pausing and fast-forward keep the two in step
+use std::io;
+
+use crate::beeper::{ self, Beeper, Producer };
+use crate::config::Config;
+use crate::movie::SoundEvent;
+use crate::timing::FRAME_HZ;
+use crate::tone::{ Tone, SAMPLE_RATE };
+use crate::wav::WavWriter;
+
+pub struct Capture {
+    beeper: Beeper,
+    events: Producer,
+    wav: WavWriter,
+    samples: Vec<f32>,
+    // Timer ticks captured so far, and the samples written for them
+    ticks: u64,
+    written: u64,
+}
+
+impl Capture {
+    pub fn create(path: &str, config: &Config) -> io::Result<Capture> {
+        let (events, consumer) = beeper::channel();
+        let mut beeper = Beeper::new(Tone::new(config.waveform, config.tone_hz, config.volume, SAMPLE_RATE), consumer);
+        beeper.set_silenced(config.mute);
+
+        Ok(Capture {
+            beeper,
+            events,
+            wav: WavWriter::create(path, SAMPLE_RATE)?,
+            samples: Vec::new(),
+            ticks: 0,
+            written: 0,
+        })
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn set_silenced(&mut self, silenced: bool) {
+        self.beeper.set_silenced(silenced);
+    }
+
+    // Writes the sound for some timer ticks of emulated time,
+    // with the sound changes that happened over them
+    pub fn run(&mut self, events: &[SoundEvent], ticks: u32) -> io::Result<()> {
+        // Ticks don't all get the same number of samples,
+        // so the audio doesn't drift from the video over time
+        self.ticks += ticks as u64;
+        let end = (self.ticks as f64 * SAMPLE_RATE as f64 / FRAME_HZ).round() as u64;
+        let length = end - self.written;
+
+        for event in events {
+            if !self.events.push(self.written + (event.at * length as f64) as u64, event.on) {
+                eprintln!("chip8: sound queue full, dropping events");
+            }
+        }
+
+        self.samples.resize(length as usize, 0.0);
+        self.beeper.fill(&mut self.samples);
+        self.written = end;
+
+        self.wav.write(&self.samples)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.wav.finish()
+    }
+}
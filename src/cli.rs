@@ -0,0 +1,221 @@
+// Command line parsing
+// Flags are shorthands for settings, so `--clock 700` is the same as
+// `clock=700` and gets checked by Config like every other option
+use std::path::Path;
+
+use crate::commands::{ DEFAULT_BENCH_FRAMES, DEFAULT_TEST_FRAMES };
+
+pub const USAGE: &str = "\
+Usage: chip8 [COMMAND] [OPTIONS] [ROM] [KEY=VALUE...]
+
+Commands:
+  run       Play the ROM (the default). Without a ROM, or given a directory,
+            pick one in a ROM browser, by default of roms/ (SDL only)
+  disasm    Print an assembly listing of the ROM
+  info      Print the ROM's size, hashes, database entry and opcode usage
+  bench     Run the ROM without a window as fast as possible and report the speed
+  test      Run test ROMs and compare the screens they end on, by default
+            every .ch8 in tests/
+
+Options:
+  -c, --clock <HZ>         Instructions per second (default 540)
+  -q, --quirks <PRESET>    Opcode behaviour: cosmac, chip48, schip or modern (default)
+  -s, --scale <N>          Starting window size as a multiple of 64x32 (default 10)
+  -p, --palette <NAME>     Colours: classic, amber, green, lcd or high-contrast
+  -k, --keymap <FILE>      Key bindings, a file or one of cosmac, hex, numpad
+      --seed <N>           Seed for the random number generator
+  -f, --fullscreen         Start fullscreen
+      --frontend <NAME>    sdl, terminal or headless
+  -m, --mute               Start with sound muted
+      --no-audio           Don't open a sound device
+      --paused             Start paused
+  -w, --watch              Reload the ROM when it changes on disk, watch=replay
+                           also replays the inputs so far to get back to the same spot
+  -o, --option <KEY=VALUE> Any other setting
+  -h, --help               Print this help
+  -V, --version            Print the version
+
+disasm and info:
+  load_address=<ADDR>      Where the ROM is loaded, the only setting they take
+
+bench and test:
+      --frames <N>         Frames of emulated time to run (default 6000 and 300)
+      --bless              test: save the screen of ROMs that have nothing to compare with
+
+Esc goes back to the ROM browser while playing.
+
+Any setting can also be given as KEY=VALUE after the ROM, e.g. `vsync=on`.
+Settings are read from chip8.cfg, the ROM database (romdb.cfg) and <ROM>.cfg
+first, the command line wins.
+";
+
+pub enum Invocation {
+    Run {
+        // None opens the ROM browser
+        rom: Option<String>,
+        // Settings in key=value form, in the order they were given
+        options: Vec<String>,
+    },
+    // Options can only set load_address, nothing else changes a listing
+    Disasm {
+        rom: String,
+        options: Vec<String>,
+    },
+    Info {
+        rom: String,
+        options: Vec<String>,
+    },
+    Bench {
+        rom: String,
+        options: Vec<String>,
+        frames: u32,
+    },
+    Test {
+        // Empty runs everything in TEST_DIR
+        roms: Vec<String>,
+        options: Vec<String>,
+        frames: u32,
+        bless: bool,
+    },
+    Help,
+    Version,
+}
+
+const COMMANDS: [&str; 5] = ["run", "disasm", "info", "bench", "test"];
+
+// Flags that take a value, and the setting they stand for
+const VALUE_FLAGS: [(&str, &str, &str); 8] = [
+    ("-c", "--clock", "clock"),
+    ("-q", "--quirks", "quirks"),
+    ("-s", "--scale", "scale"),
+    ("-p", "--palette", "palette"),
+    ("-k", "--keymap", "keymap"),
+    ("", "--seed", "seed"),
+    ("", "--frontend", "frontend"),
+    ("-o", "--option", ""),
+];
+
+// Flags on their own, and the setting they make
+const SWITCHES: [(&str, &str, &str); 5] = [
+    ("-f", "--fullscreen", "fullscreen=on"),
+    ("-m", "--mute", "mute=on"),
+    ("", "--no-audio", "audio=off"),
+    ("", "--paused", "paused=on"),
+    ("-w", "--watch", "watch=on"),
+];
+
+// Takes the arguments without the program name
+pub fn parse(args: &[String]) -> Result<Invocation, String> {
+    // Without a command it's run, so `chip8 roms/PONG` still works
+    let (command, args) = match args.first() {
+        Some(first) if COMMANDS.contains(&first.as_str()) => (first.as_str(), &args[1..]),
+        _ => ("run", args),
+    };
+
+    let mut roms = Vec::new();
+    let mut options = Vec::new();
+    let mut frames = None;
+    let mut bless = false;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let arg = arg.as_str();
+
+        match arg {
+            "-h" | "--help" => return Ok(Invocation::Help),
+            "-V" | "--version" => return Ok(Invocation::Version),
+            "--bless" if command == "test" => {
+                bless = true;
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some(&(_, _, setting)) = SWITCHES.iter().find(|&&(short, long, _)| arg == short || arg == long) {
+            options.push(setting.to_string());
+            continue;
+        }
+
+        // --flag value, or --flag=value for the long forms
+        let (flag, inline) = match arg.find('=') {
+            Some(i) if arg.starts_with("--") => (&arg[..i], Some(&arg[i + 1..])),
+            _ => (arg, None),
+        };
+
+        if flag == "--frames" && (command == "bench" || command == "test") {
+            let value = match inline {
+                Some(value) => value,
+                None => args.next().ok_or("--frames needs a value")?,
+            };
+
+            frames = Some(value.parse().ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| format!("--frames must be a positive number, got `{}`", value))?);
+            continue;
+        }
+
+        if let Some(&(_, long, key)) = VALUE_FLAGS.iter().find(|&&(short, long, _)| flag == short || flag == long) {
+            let value = match inline {
+                Some(value) => value,
+                None => args.next().ok_or_else(|| format!("{} needs a value", long))?,
+            };
+
+            // -o passes a setting straight through
+            options.push(if key.is_empty() { value.to_string() } else { format!("{}={}", key, value) });
+            continue;
+        }
+
+        if arg.starts_with('-') && arg.len() > 1 {
+            return Err(format!("unknown option `{}` for {}", arg, command));
+        }
+
+        if arg.contains('=') {
+            options.push(arg.to_string());
+        } else {
+            roms.push(arg.to_string());
+        }
+    }
+
+    // A directory to run opens the ROM browser there
+    if command == "run" && roms.len() == 1 && Path::new(&roms[0]).is_dir() {
+        options.push(format!("rom_dir={}", roms.remove(0)));
+    }
+
+    for rom in &roms {
+        if !Path::new(rom).is_file() {
+            return Err(format!("no such ROM `{}`", rom));
+        }
+    }
+
+    if command == "test" {
+        return Ok(Invocation::Test {
+            roms,
+            options,
+            frames: frames.unwrap_or(DEFAULT_TEST_FRAMES),
+            bless,
+        });
+    }
+
+    if command == "run" && roms.len() <= 1 {
+        return Ok(Invocation::Run { rom: roms.pop(), options });
+    }
+
+    let rom = match roms.len() {
+        0 => return Err("no ROM given".to_string()),
+        1 => roms.remove(0),
+        _ => return Err(format!("{} takes one ROM, got {}", command, roms.len())),
+    };
+
+    if command == "disasm" || command == "info" {
+        if let Some(option) = options.iter().find(|o| o.split('=').next().unwrap_or("").trim() != "load_address") {
+            return Err(format!("{} only takes load_address, got `{}`", command, option));
+        }
+    }
+
+    Ok(match command {
+        "disasm" => Invocation::Disasm { rom, options },
+        "info" => Invocation::Info { rom, options },
+        _ => Invocation::Bench { rom, options, frames: frames.unwrap_or(DEFAULT_BENCH_FRAMES) },
+    })
+}
+
@@ -0,0 +1,291 @@
+// The subcommands other than run
+// They all work without a window, so they can be used in scripts and CI
+use std::collections::HashMap;
+use std::fs;
+use std::io::{ self, Write };
+use std::panic::{ self, AssertUnwindSafe };
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::config::Config;
+use crate::cpu::CPU;
+use crate::disasm::{ self, Platform };
+use crate::display::{ CHIP8_HEIGHT, CHIP8_WIDTH };
+use crate::movie::{ self, Frame };
+use crate::romdb::{ self, RomDb };
+use crate::timing::{ Clock, FRAME_HZ };
+
+// Where `chip8 test` looks when it isn't given any ROMs
+pub const TEST_DIR: &str = "tests";
+pub const TEST_ROM_EXT: &str = "ch8";
+
+// Holds the hash of the screen a test ROM should end on
+pub const EXPECT_EXT: &str = "expect";
+
+// Test ROMs run for this long unless told otherwise
+pub const DEFAULT_TEST_FRAMES: u32 = 300;
+pub const DEFAULT_BENCH_FRAMES: u32 = 6000;
+
+fn read_rom(rom: &str) -> Result<Vec<u8>, String> {
+    fs::read(rom).map_err(|e| format!("could not read {}: {}", rom, e))
+}
+
+// Prints a listing of the whole ROM, one word per line
+pub fn disasm(rom: &str, config: &Config) -> Result<(), String> {
+    let bytes = read_rom(rom)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let listed = bytes.chunks(2).enumerate().try_for_each(|(i, word)| {
+        let addr = config.load_address + i * 2;
+
+        if word.len() == 1 {
+            return writeln!(out, "{:03X}  {:02X}    DB #{:02X}", addr, word[0], word[0]);
+        }
+
+        let opcode = (word[0] as u16) << 8 | word[1] as u16;
+
+        match disasm::decode(opcode) {
+            Some(ins) => writeln!(out, "{:03X}  {:04X}  {}", addr, opcode, ins.text),
+            None => writeln!(out, "{:03X}  {:04X}  DW #{:04X}", addr, opcode, opcode),
+        }
+    });
+
+    written(listed)
+}
+
+pub fn info(rom: &str, config: &Config) -> Result<(), String> {
+    let bytes = read_rom(rom)?;
+    let mut platform = Platform::Chip8;
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+
+    // The platform only goes by code that can run, sprite data often
+    // happens to look like SUPER-CHIP and XO-CHIP opcodes
+    let code = disasm::reachable(&bytes, config.load_address);
+
+    for (offset, &reached) in code.iter().enumerate() {
+        let ins = match bytes.get(offset..offset + 2) {
+            Some(word) if reached => disasm::decode((word[0] as u16) << 8 | word[1] as u16),
+            _ => None,
+        };
+
+        if let Some(ins) = ins.filter(|ins| ins.platform > platform) {
+            platform = ins.platform;
+        }
+    }
+
+    // Every aligned word is counted, so sprite data that happens
+    // to decode is counted too
+    for word in bytes.chunks_exact(2) {
+        if let Some(ins) = disasm::decode((word[0] as u16) << 8 | word[1] as u16) {
+            *counts.entry(ins.pattern).or_insert(0) += 1;
+        }
+    }
+
+    let db = RomDb::load()?;
+    let entry = db.lookup(&bytes);
+
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let printed = (|| {
+        writeln!(out, "file      {}", rom)?;
+        writeln!(out, "size      {} bytes", bytes.len())?;
+        writeln!(out, "hash      {:016x}", movie::hash_bytes(&bytes))?;
+        writeln!(out, "sha1      {}", romdb::sha1(&bytes))?;
+
+        if let Some(title) = entry.and_then(|e| e.title.as_ref()) {
+            writeln!(out, "title     {}", title)?;
+        }
+
+        // The database knows better than guessing from the opcodes
+        match entry.and_then(|e| e.platform.as_ref()) {
+            Some(known) => writeln!(out, "platform  {}", known)?,
+            None => writeln!(out, "platform  {} (guessed)", platform.name())?,
+        }
+
+        if let Some(description) = entry.and_then(|e| e.description.as_ref()) {
+            writeln!(out, "about     {}", description)?;
+        }
+
+        if let Some(entry) = entry.filter(|e| !e.settings.is_empty()) {
+            writeln!(out, "settings  {}", entry.settings.join("; "))?;
+        }
+
+        writeln!(out, "opcodes   every aligned word, data included")?;
+
+        let most = counts.first().map_or(1, |&(_, count)| count);
+
+        for &(pattern, count) in &counts {
+            let bar = "#".repeat((count * 40).div_ceil(most));
+            writeln!(out, "  {}  {:5}  {}", pattern, count, bar)?;
+        }
+
+        Ok(())
+    })();
+
+    written(printed)
+}
+
+// Output going to a reader that stopped early, like `| head`, is fine
+fn written(result: io::Result<()>) -> Result<(), String> {
+    match result {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(format!("could not write output: {}", e)),
+        _ => Ok(()),
+    }
+}
+
+// Runs the ROM with no input, as fast as possible
+pub fn bench(rom: &str, config: &Config, frames: u32) -> Result<(), String> {
+    let mut cpu = load(rom, config)?;
+
+    let start = Instant::now();
+    let cycles = guarded(|| run_frames(&mut cpu, config, frames))
+        .map_err(|message| format!("{} crashed: {}", rom, message))?;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let emulated = frames as f64 / FRAME_HZ;
+
+    println!("{} frames, {} instructions in {:.3}s", frames, cycles, elapsed);
+    println!("{:.0} instructions/s, {:.1}x real time", cycles as f64 / elapsed, emulated / elapsed);
+
+    Ok(())
+}
+
+// Runs every test ROM and compares the screen it ends on with the expected one.
+// With bless, ROMs without an expected screen get the one they ended on.
+// Returns whether they all passed
+pub fn test(roms: &[String], options: &[String], frames: u32, bless: bool) -> Result<bool, String> {
+    let roms = if roms.is_empty() { find_tests()? } else { roms.iter().map(PathBuf::from).collect() };
+
+    if roms.is_empty() {
+        return Err(format!("no test ROMs in {}", TEST_DIR));
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+
+    for rom in &roms {
+        let name = rom.to_string_lossy();
+        let config = Config::load(&name, options)?;
+
+        let mut cpu = match load(&name, &config) {
+            Ok(cpu) => cpu,
+            Err(e) => {
+                println!("FAIL  {}", e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let ran = guarded(|| {
+            run_frames(&mut cpu, &config, frames);
+            cpu.gfx
+        });
+
+        let gfx = match ran {
+            Ok(gfx) => gfx,
+            Err(message) => {
+                println!("CRASH {}: {}", name, message);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let hash = format!("{:016x}", movie::hash_gfx(&gfx));
+        let expect_path = rom.with_extension(EXPECT_EXT);
+
+        match fs::read_to_string(&expect_path) {
+            Ok(expected) if expected.trim() == hash => {
+                println!("PASS  {}", name);
+                passed += 1;
+            }
+            Ok(expected) => {
+                println!("FAIL  {}: screen {} expected {}", name, hash, expected.trim());
+                print_screen(&gfx);
+                failed += 1;
+            }
+            Err(_) if bless => {
+                fs::write(&expect_path, format!("{}\n", hash))
+                    .map_err(|e| format!("could not write {}: {}", expect_path.display(), e))?;
+
+                println!("NEW   {}: wrote {}", name, expect_path.display());
+                print_screen(&gfx);
+                passed += 1;
+            }
+            Err(_) => {
+                println!("FAIL  {}: no {}, run with --bless to accept this screen", name, expect_path.display());
+                print_screen(&gfx);
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+
+    Ok(failed == 0)
+}
+
+// The CPU panics on opcodes it doesn't know and on a bad stack, that comes back
+// as Err with the panic message instead of the message going to the terminal
+fn guarded<T>(run: impl FnOnce() -> T) -> Result<T, String> {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let ran = panic::catch_unwind(AssertUnwindSafe(run));
+    panic::set_hook(hook);
+
+    ran.map_err(|e| {
+        e.downcast_ref::<String>().cloned()
+            .or_else(|| e.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_default()
+    })
+}
+
+fn find_tests() -> Result<Vec<PathBuf>, String> {
+    let entries = fs::read_dir(TEST_DIR)
+        .map_err(|e| format!("could not read {}: {}", TEST_DIR, e))?;
+
+    let mut roms: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == TEST_ROM_EXT))
+        .collect();
+
+    roms.sort();
+    Ok(roms)
+}
+
+fn load(rom: &str, config: &Config) -> Result<CPU, String> {
+    let mut cpu = CPU::new();
+    cpu.load_path(rom, config.load_address).map_err(|e| format!("{}: {}", rom, e))?;
+    cpu.quirks = config.quirks;
+
+    // Runs have to be repeatable
+    cpu.seed(config.seed.unwrap_or(0));
+    Ok(cpu)
+}
+
+// Runs whole frames of emulated time with no keys pressed,
+// returns how many instructions ran
+fn run_frames(cpu: &mut CPU, config: &Config, frames: u32) -> u64 {
+    let mut clock = Clock::new(config.clock_hz);
+    let mut keys = [false; 16];
+    let mut cycles = 0;
+
+    for _ in 0..frames {
+        let frame = Frame::new(clock.advance_by(Clock::frame_time()), &mut Vec::new());
+        frame.run(cpu, &mut keys);
+        cycles += frame.ticks.cycles as u64;
+    }
+
+    cycles
+}
+
+fn print_screen(gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
+    for row in gfx.iter() {
+        let line: String = row.iter().map(|&p| if p != 0 { '#' } else { '.' }).collect();
+        println!("      {}", line);
+    }
+}
@@ -0,0 +1,466 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cpu::{ MEMORY_SIZE, PROGRAM_START };
+use crate::display::{ Persistence, DEFAULT_SCALE, MAX_SCALE, PERSISTENCE_MODES, DEFAULT_DECAY };
+use crate::effects::Effects;
+#[cfg(feature = "sdl")]
+use crate::keymap::{ self, Keymap, PadMap, PLAYERS };
+use crate::palette::{ self, Palette };
+use crate::quirks::{ self, Quirks };
+use crate::romdb::RomDb;
+use crate::tone::{ self, Waveform, DEFAULT_FREQUENCY, DEFAULT_VOLUME, MIN_FREQUENCY, MAX_FREQUENCY };
+use crate::video::{ VideoFormat, DEFAULT_VIDEO_SCALE };
+use crate::watch::{ Watch, WATCH_MODES };
+use crate::timing::{ DEFAULT_CLOCK_HZ, FAST_FORWARD_STEPS, UNTHROTTLED };
+
+// Lowest and highest clock speeds we accept, in instructions per second
+pub const MIN_CLOCK_HZ: u32 = 60;
+pub const MAX_CLOCK_HZ: u32 = 100_000;
+
+// Global settings file, unless CHIP8_CONFIG points somewhere else
+pub const CONFIG_FILE: &str = "chip8.cfg";
+
+// Per-ROM settings live next to the ROM, e.g. roms/PONG.cfg
+pub const ROM_CONFIG_EXT: &str = "cfg";
+
+// Where the ROM browser looks unless rom_dir says otherwise
+pub const DEFAULT_ROM_DIR: &str = "roms";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Frontend {
+    // A window with sound and controllers
+    Sdl,
+    // Half blocks in a terminal, for when there's no window
+    Terminal,
+    // No input or output at all, plays a movie back as fast as possible
+    // and writes whatever video and audio were asked for
+    Headless,
+}
+
+// Runtime settings
+// Options are key=value pairs, one per line in a settings file
+// (lines starting with # are comments) or after the ROM on the command line,
+// e.g. `chip8 roms/PONG clock=700`
+//
+// They're applied in order: defaults, the global settings file,
+// the ROM's entry in the database, the ROM's settings file, then the command line
+pub struct Config {
+    pub clock_hz: u32,
+    pub vsync: bool,
+    pub fast_forward: u32,
+    pub slow_motion: f64,
+    pub start_paused: bool,
+    pub quirks: Quirks,
+    // Where the ROM goes in memory and starts running
+    pub load_address: usize,
+    #[cfg(feature = "sdl")]
+    pub keymap: Keymap,
+    #[cfg(feature = "sdl")]
+    pub pads: [PadMap; PLAYERS],
+    // Random when not given
+    pub seed: Option<u64>,
+    pub record: Option<String>,
+    pub play: Option<String>,
+    pub palette: Palette,
+    pub scale: u32,
+    pub fullscreen: bool,
+    pub stretch: bool,
+    pub persistence: Persistence,
+    pub decay: f32,
+    pub effects: Effects,
+    // Where screenshots and hotkey recordings are saved
+    pub capture_dir: String,
+    // Screenshots at 64x32 instead of the window's scale
+    pub screenshot_native: bool,
+    // Record video from the start, - streams to stdout
+    pub video: Option<String>,
+    // Guessed from the file name when not given
+    pub video_format: Option<VideoFormat>,
+    pub video_scale: usize,
+    // The beep
+    pub tone_hz: f32,
+    pub volume: f32,
+    pub waveform: Waveform,
+    pub mute: bool,
+    // Off runs without a sound device even if there is one
+    pub audio: bool,
+    // Also write the sound to a WAV file
+    pub wav: Option<String>,
+    pub frontend: Frontend,
+    // Ring the terminal bell for the sound timer
+    pub bell: bool,
+    // Reload the ROM when it changes on disk
+    pub watch: Watch,
+    // Where the ROM browser looks
+    pub rom_dir: String,
+    // From the ROM database, not a setting
+    pub title: Option<String>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            clock_hz: DEFAULT_CLOCK_HZ,
+            vsync: false,
+            fast_forward: 4,
+            slow_motion: 0.5,
+            start_paused: false,
+            quirks: Quirks::new(),
+            load_address: PROGRAM_START,
+            #[cfg(feature = "sdl")]
+            keymap: Keymap::new(),
+            #[cfg(feature = "sdl")]
+            pads: [PadMap::standard(), PadMap::new()],
+            seed: None,
+            record: None,
+            play: None,
+            palette: Palette::new(),
+            scale: DEFAULT_SCALE,
+            fullscreen: false,
+            stretch: false,
+            persistence: Persistence::Off,
+            decay: DEFAULT_DECAY,
+            effects: Effects::new(),
+            capture_dir: ".".to_string(),
+            screenshot_native: false,
+            video: None,
+            video_format: None,
+            video_scale: DEFAULT_VIDEO_SCALE,
+            tone_hz: DEFAULT_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            waveform: Waveform::Square,
+            mute: false,
+            audio: true,
+            wav: None,
+            frontend: if cfg!(feature = "sdl") { Frontend::Sdl } else { Frontend::Terminal },
+            bell: true,
+            watch: Watch::Off,
+            rom_dir: DEFAULT_ROM_DIR.to_string(),
+            title: None,
+        }
+    }
+
+    // Builds the settings for a ROM from every source
+    pub fn load(rom: &str, args: &[String]) -> Result<Config, String> {
+        let mut config = Config::new();
+
+        config.load_global()?;
+        config.load_romdb(rom)?;
+        config.load_optional_file(&Path::new(rom).with_extension(ROM_CONFIG_EXT))?;
+        config.apply_args(args)?;
+
+        Ok(config)
+    }
+
+    // The settings before a ROM has been picked, for the ROM browser
+    pub fn load_without_rom(args: &[String]) -> Result<Config, String> {
+        let mut config = Config::new();
+
+        config.load_global()?;
+        config.apply_args(args)?;
+
+        Ok(config)
+    }
+
+    // A file named by CHIP8_CONFIG has to be there, the default one doesn't
+    fn load_global(&mut self) -> Result<(), String> {
+        match env::var("CHIP8_CONFIG") {
+            Ok(path) => self.load_file(Path::new(&path)),
+            Err(_) => self.load_optional_file(Path::new(CONFIG_FILE)),
+        }
+    }
+
+    // Applies a settings file
+    pub fn load_file(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        self.apply_file(path, &text)
+    }
+
+    // Applies a settings file, if there is one
+    fn load_optional_file(&mut self, path: &Path) -> Result<(), String> {
+        match fs::read_to_string(path) {
+            Ok(text) => self.apply_file(path, &text),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("could not read {}: {}", path.display(), e)),
+        }
+    }
+
+    fn apply_file(&mut self, path: &Path, text: &str) -> Result<(), String> {
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.apply(line)
+                .map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+        }
+
+        Ok(())
+    }
+
+    // Applies the ROM's database entry, if it has one
+    fn load_romdb(&mut self, rom: &str) -> Result<(), String> {
+        let bytes = match fs::read(rom) {
+            Ok(bytes) => bytes,
+            Err(_) => return Ok(()),
+        };
+
+        let db = RomDb::load()?;
+        let entry = match db.lookup(&bytes) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        for setting in &entry.settings {
+            self.apply(setting).map_err(|e| format!("romdb: {}", e))?;
+        }
+
+        self.title = entry.title.clone();
+        Ok(())
+    }
+
+    // Applies a keymap file, which is a settings file that only binds keys:
+    // an optional `keymap = <preset>` to start from, then `key.X = ...` lines
+    #[cfg(feature = "sdl")]
+    fn load_keymap(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read keymap {}: {}", path.display(), e))?;
+
+        self.keymap = Keymap::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().unwrap_or("").trim();
+
+            // Only a preset, a keymap naming another file could loop forever
+            let result = if key == "keymap" {
+                match Keymap::preset(value) {
+                    Some(preset) => {
+                        self.keymap = preset;
+                        Ok(())
+                    }
+                    None => Err(format!("keymap in a keymap file must be one of {}, got `{}`", keymap::PRESETS.join(", "), value)),
+                }
+            } else if key.starts_with("key.") {
+                self.apply(line)
+            } else {
+                Err(format!("keymaps can only set keymap and key.X, got `{}`", key))
+            };
+
+            result.map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+        }
+
+        Ok(())
+    }
+
+    // Applies every key=value argument in order
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        for arg in args {
+            self.apply(arg)?;
+        }
+
+        Ok(())
+    }
+
+    // Applies a single key=value pair
+    pub fn apply(&mut self, option: &str) -> Result<(), String> {
+        let mut parts = option.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+
+        match parts.next() {
+            Some(value) => self.set(key, value.trim()),
+            None => Err(format!("expected key=value, got `{}`", option)),
+        }
+    }
+
+    // Sets a single option by name
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "clock" => {
+                let hz: u32 = value.parse()
+                    .map_err(|_| format!("clock must be a number, got `{}`", value))?;
+
+                if !(MIN_CLOCK_HZ..=MAX_CLOCK_HZ).contains(&hz) {
+                    return Err(format!("clock must be between {} and {} Hz", MIN_CLOCK_HZ, MAX_CLOCK_HZ));
+                }
+
+                self.clock_hz = hz;
+            }
+            "vsync" => self.vsync = parse_bool(key, value)?,
+            "fast_forward" => {
+                self.fast_forward = match value {
+                    "max" => UNTHROTTLED,
+                    _ => value.parse().ok()
+                        .filter(|x| FAST_FORWARD_STEPS.contains(x) && *x != UNTHROTTLED)
+                        .ok_or_else(|| format!("fast_forward must be 2, 4, 8, 16 or max, got `{}`", value))?,
+                };
+            }
+            "slow_motion" => {
+                self.slow_motion = value.parse().ok()
+                    .filter(|x| *x >= 0.1 && *x <= 1.0)
+                    .ok_or_else(|| format!("slow_motion must be between 0.1 and 1.0, got `{}`", value))?;
+            }
+            "paused" => self.start_paused = parse_bool(key, value)?,
+            "quirks" => {
+                self.quirks = Quirks::preset(value)
+                    .ok_or_else(|| format!("quirks must be one of {}, got `{}`", quirks::preset_names().join(", "), value))?;
+            }
+            _ if key.starts_with("quirk.") => {
+                let on = parse_bool(key, value)?;
+
+                if !self.quirks.set(&key[6..], on) {
+                    return Err(format!("unknown option `{}`", key));
+                }
+            }
+            "load_address" => {
+                let hex = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+
+                // At least one whole opcode has to fit after it
+                self.load_address = usize::from_str_radix(hex, 16).ok()
+                    .filter(|a| (PROGRAM_START..=MEMORY_SIZE - 2).contains(a))
+                    .ok_or_else(|| format!("load_address must be hex between {:X} and {:X}, got `{}`", PROGRAM_START, MEMORY_SIZE - 2, value))?;
+            }
+            "seed" => {
+                self.seed = Some(value.parse()
+                    .map_err(|_| format!("seed must be a number, got `{}`", value))?);
+            }
+            "palette" => {
+                self.palette = Palette::theme(value)
+                    .ok_or_else(|| format!("palette must be one of {}, got `{}`", palette::theme_names().join(", "), value))?;
+            }
+            "fg" => self.palette.fg = palette::parse_color(value)?,
+            "bg" => self.palette.bg = palette::parse_color(value)?,
+            "scale" => {
+                self.scale = value.parse().ok()
+                    .filter(|s| (1..=MAX_SCALE).contains(s))
+                    .ok_or_else(|| format!("scale must be between 1 and {}, got `{}`", MAX_SCALE, value))?;
+            }
+            "fullscreen" => self.fullscreen = parse_bool(key, value)?,
+            "stretch" => self.stretch = parse_bool(key, value)?,
+            "persistence" => {
+                self.persistence = PERSISTENCE_MODES.iter()
+                    .find(|&&(name, _)| name == value)
+                    .map(|&(_, mode)| mode)
+                    .ok_or_else(|| format!("persistence must be off, fade or blend, got `{}`", value))?;
+            }
+            "decay" => {
+                self.decay = value.parse().ok()
+                    .filter(|d| (0.0..1.0).contains(d))
+                    .ok_or_else(|| format!("decay must be between 0.0 and 1.0, got `{}`", value))?;
+            }
+            "scanlines" => self.effects.scanlines = parse_bool(key, value)?,
+            "grid" => self.effects.grid = parse_bool(key, value)?,
+            "dots" => self.effects.dots = parse_bool(key, value)?,
+            "bloom" => self.effects.bloom = parse_bool(key, value)?,
+            "vignette" => self.effects.vignette = parse_bool(key, value)?,
+            "capture_dir" => self.capture_dir = value.to_string(),
+            "screenshot_scale" => {
+                self.screenshot_native = match value {
+                    "native" => true,
+                    "display" => false,
+                    _ => return Err(format!("screenshot_scale must be native or display, got `{}`", value)),
+                };
+            }
+            "video" => self.video = Some(value.to_string()),
+            "video_format" => {
+                self.video_format = Some(VideoFormat::from_name(value)
+                    .ok_or_else(|| format!("video_format must be gif, y4m or rgb, got `{}`", value))?);
+            }
+            "video_scale" => {
+                self.video_scale = value.parse().ok()
+                    .filter(|s| (1..=MAX_SCALE as usize).contains(s))
+                    .ok_or_else(|| format!("video_scale must be between 1 and {}, got `{}`", MAX_SCALE, value))?;
+            }
+            "tone" => {
+                self.tone_hz = value.parse().ok()
+                    .filter(|f| (MIN_FREQUENCY..=MAX_FREQUENCY).contains(f))
+                    .ok_or_else(|| format!("tone must be between {} and {} Hz, got `{}`", MIN_FREQUENCY, MAX_FREQUENCY, value))?;
+            }
+            "volume" => {
+                self.volume = value.parse().ok()
+                    .filter(|v| (0.0..=1.0).contains(v))
+                    .ok_or_else(|| format!("volume must be between 0.0 and 1.0, got `{}`", value))?;
+            }
+            "waveform" => {
+                self.waveform = tone::WAVEFORMS.iter()
+                    .find(|&&(name, _)| name == value)
+                    .map(|&(_, waveform)| waveform)
+                    .ok_or_else(|| format!("waveform must be square, triangle, sine or noise, got `{}`", value))?;
+            }
+            "mute" => self.mute = parse_bool(key, value)?,
+            "audio" => self.audio = parse_bool(key, value)?,
+            "frontend" => {
+                self.frontend = match value {
+                    "terminal" => Frontend::Terminal,
+                    "headless" => Frontend::Headless,
+                    "sdl" if cfg!(feature = "sdl") => Frontend::Sdl,
+                    "sdl" => return Err("this build has no SDL frontend".to_string()),
+                    _ => return Err(format!("frontend must be sdl, terminal or headless, got `{}`", value)),
+                };
+            }
+            "wav" => self.wav = Some(value.to_string()),
+            "bell" => self.bell = parse_bool(key, value)?,
+            "watch" => {
+                self.watch = match value {
+                    // Plain on just reloads
+                    "on" => Watch::Restart,
+                    _ => WATCH_MODES.iter()
+                        .find(|&&(name, _)| name == value)
+                        .map(|&(_, mode)| mode)
+                        .ok_or_else(|| format!("watch must be on, off, restart or replay, got `{}`", value))?,
+                };
+            }
+            "rom_dir" => self.rom_dir = value.to_string(),
+            "record" => self.record = Some(value.to_string()),
+            "play" => self.play = Some(value.to_string()),
+            // A preset, or a file of key bindings
+            #[cfg(feature = "sdl")]
+            "keymap" => {
+                self.keymap = match Keymap::preset(value) {
+                    Some(preset) => preset,
+                    None if Path::new(value).is_file() => return self.load_keymap(Path::new(value)),
+                    None => return Err(format!("keymap must be one of {} or a file, got `{}`", keymap::PRESETS.join(", "), value)),
+                };
+            }
+            #[cfg(feature = "sdl")]
+            _ if key.starts_with("key.") => {
+                let (index, scancodes) = keymap::parse_binding(&key[4..], value)?;
+                self.keymap.bind(index, &scancodes);
+            }
+            #[cfg(feature = "sdl")]
+            _ if key.starts_with("pad") => {
+                let (player, index, inputs) = keymap::parse_pad_binding(&key[3..], value)?;
+                self.pads[player].bind(index, &inputs);
+            }
+            // Bindings only mean something to the SDL frontend, so settings
+            // files shared with an SDL build still load without it
+            #[cfg(not(feature = "sdl"))]
+            _ if key == "keymap" || key.starts_with("key.") || key.starts_with("pad") => {}
+            _ => return Err(format!("unknown option `{}`", key)),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "on" | "true" | "yes" | "1" => Ok(true),
+        "off" | "false" | "no" | "0" => Ok(false),
+        _ => Err(format!("{} must be on or off, got `{}`", key, value)),
+    }
+}
@@ -1,9 +1,44 @@
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
-use std::io::prelude::*;
+use std::io::{ self, Read };
+use std::path::Path;
 
 use crate::display::{ FONT_SET, CHIP8_HEIGHT, CHIP8_WIDTH };
+use crate::quirks::Quirks;
 
-use rand::Rng;
+use rand::{ Rng, SeedableRng, StdRng };
+
+pub const MEMORY_SIZE: usize = 4096;
+
+// Where programs are normally loaded and start running
+pub const PROGRAM_START: usize = 0x200;
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    // The ROM doesn't fit between the load address and the end of memory
+    TooLarge { size: usize, max: usize },
+    Empty,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref e) => write!(f, "could not read ROM: {}", e),
+            LoadError::TooLarge { size, max } => write!(f, "ROM is {} bytes, only {} fit in memory", size, max),
+            LoadError::Empty => write!(f, "ROM is empty"),
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
 
 pub struct CPU {
     pub opcode: u16,
@@ -18,8 +53,12 @@ pub struct CPU {
     pub keypad: [bool; 16],
     pub key_wait: bool,
     pub key_wait_reg: usize,
+    // Key pressed while waiting, FX0A completes once it's released
+    pub key_wait_key: Option<usize>,
     pub gfx: [[u8; CHIP8_WIDTH]; CHIP8_HEIGHT],
     pub draw_flag: bool,
+    pub rng: StdRng,
+    pub quirks: Quirks,
 }
 
 impl CPU {
@@ -37,45 +76,79 @@ impl CPU {
             keypad: [false; 16],
             key_wait: false,
             key_wait_reg: 0usize,
+            key_wait_key: None,
             gfx: [[0; CHIP8_WIDTH]; CHIP8_HEIGHT],
             draw_flag: false,
+            rng: StdRng::from_seed(&[rand::random::<usize>()][..]),
+            quirks: Quirks::new(),
         }
     }
 
-    pub fn initialize(&mut self, game: String) {
-        self.pc = 0x200;
+    // Reseeds the random number generator used by CXKK
+    // so a run can be reproduced exactly
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::from_seed(&[(seed >> 32) as usize, seed as usize][..]);
+    }
+
+    // Puts the CPU back to its power-on state, with only the font in memory
+    pub fn reset(&mut self) {
+        self.pc = PROGRAM_START;
         self.opcode = 0;
         self.i = 0;
         self.sp = 0;
+        self.v = [0u8; 16];
 
         // Clear display, stack, registers, and memory
         self.stack = [0u16; 16];
-        self.memory = [0u8; 4096];
+        self.memory = [0u8; MEMORY_SIZE];
+        self.gfx = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        self.memory[..FONT_SET.len()].copy_from_slice(&FONT_SET);
+
+        self.keypad = [false; 16];
+        self.key_wait = false;
+        self.key_wait_key = None;
+
+        // Reset timers
+        self.delay_timer = 0u8;
+        self.sound_timer = 0u8;
+    }
 
-        for i in 0..79 {
-            self.memory[i] = FONT_SET[i];
+    // Resets the CPU and loads a program at address, which is where it starts running
+    pub fn load_bytes(&mut self, rom: &[u8], address: usize) -> Result<(), LoadError> {
+        let max = MEMORY_SIZE.saturating_sub(address);
+
+        if rom.is_empty() {
+            return Err(LoadError::Empty);
         }
 
-        let mut game_file = File::open(game).expect("Game not found");
-        let mut buffer: Vec<u8> = Vec::new();
+        if rom.len() > max {
+            return Err(LoadError::TooLarge { size: rom.len(), max });
+        }
 
-        // Read the game file into a buffer
-        game_file.read_to_end(&mut buffer).expect("Error reading game");
+        self.reset();
+        self.memory[address..address + rom.len()].copy_from_slice(rom);
+        self.pc = address;
 
-        // Populate game into memory
-        for (i, &byte) in buffer.iter().enumerate() {
-            let addr_pos = 0x200 + i;
+        Ok(())
+    }
 
-            if addr_pos < 4096 {
-                self.memory[addr_pos] = byte;
-            } else {
-                break;
-            }
+    pub fn load_reader<R: Read>(&mut self, mut reader: R, address: usize) -> Result<(), LoadError> {
+        // One byte more than fits is enough to know it's too large
+        let max = MEMORY_SIZE.saturating_sub(address);
+        let mut buffer = Vec::new();
+        reader.by_ref().take(max as u64 + 1).read_to_end(&mut buffer)?;
+
+        if buffer.len() > max {
+            // Carry on counting so the error gives the real size
+            let rest = io::copy(&mut reader, &mut io::sink())? as usize;
+            return Err(LoadError::TooLarge { size: buffer.len() + rest, max });
         }
 
-        // Reset timers
-        self.delay_timer = 0u8;
-        self.sound_timer = 0u8;
+        self.load_bytes(&buffer, address)
+    }
+
+    pub fn load_path<P: AsRef<Path>>(&mut self, path: P, address: usize) -> Result<(), LoadError> {
+        self.load_reader(File::open(path)?, address)
     }
 
     // emulate_cycle
@@ -83,15 +156,24 @@ impl CPU {
     // updates the timers as well
     pub fn emulate_cycle(&mut self, keypad: [bool; 16]) {
         // Store the keypad for opcodes to access it
+        let previous = self.keypad;
         self.keypad = keypad;
 
         // If we're waiting for a keypress, then skip opcode execution
-        // and register the keypress
+        // and register the keypress.
+        // Like the original interpreter this waits for a key to be pressed
+        // and then released, and keys already held when FX0A ran don't count
         if self.key_wait {
-            for (i, &key) in keypad.iter().enumerate() {
-                if key {
-                    self.key_wait = false;
-                    self.v[self.key_wait_reg] = i as u8;
+            match self.key_wait_key {
+                None => {
+                    self.key_wait_key = (0..16).find(|&i| keypad[i] && !previous[i]);
+                }
+                Some(key) => {
+                    if !keypad[key] {
+                        self.key_wait = false;
+                        self.key_wait_key = None;
+                        self.v[self.key_wait_reg] = key as u8;
+                    }
                 }
             }
         } else {
@@ -100,8 +182,9 @@ impl CPU {
             // We read in one byte, then shift left 8 bits
             // then read the next byte and bitwise-OR it to grab the full word
             // and store it
-            let extracted_op: u16 = (self.memory[self.pc] as u16) << 8 
-                                | self.memory[self.pc + 1] as u16;
+            // Addresses wrap at the end of memory like on the original
+            let extracted_op: u16 = (self.memory[self.pc & 0xFFF] as u16) << 8
+                                | self.memory[(self.pc + 1) & 0xFFF] as u16;
 
             // Store that in CPU's opcode
             self.opcode = extracted_op;
@@ -113,14 +196,13 @@ impl CPU {
 
     }
 
-    // TODO: Legacy mode?
-    // Opcodes 8XY6, 8XYE, FX55, and FX65 are debated to have different functionality
-    // Newer roms work with only one spec, while older games work with the other
+    // Opcodes 8XY1-3, 8XY6, 8XYE, BNNN, DXYN, FX55, and FX65 are debated to have
+    // different functionality, newer roms work with only one spec while older
+    // games work with the other. self.quirks picks which
     //
     // decodes the opcode and matches based on the first nibble
     // then executes the correct opcode function
     pub fn decode_opcode(&mut self) {
-        println!("{:X}", self.opcode);
         match (self.opcode & 0xF000) >> 12 {
             0x0 => self.decode_0(),
             0x1 => self.oc_1nnn(),
@@ -308,6 +390,10 @@ impl CPU {
 
         self.v[x] |= self.v[y];
 
+        if self.quirks.vf_reset {
+            self.v[15] = 0;
+        }
+
         self.pc += 2;
     }
 
@@ -318,6 +404,10 @@ impl CPU {
 
         self.v[x] &= self.v[y];
 
+        if self.quirks.vf_reset {
+            self.v[15] = 0;
+        }
+
         self.pc += 2;
     }
     // Vx = Vx XOR Vy
@@ -327,6 +417,10 @@ impl CPU {
 
         self.v[x] ^= self.v[y];
 
+        if self.quirks.vf_reset {
+            self.v[15] = 0;
+        }
+
         self.pc += 2;
     }
 
@@ -364,13 +458,15 @@ impl CPU {
         self.pc += 2;
     }
 
-    // If LSB = 1, then Vf = 1
+    // Vx = Vx >> 1 (or Vy >> 1)
+    // Set Vf to the bit shifted out
     fn oc_8xy6(&mut self) {
         let x = ((self.opcode & 0x0F00) >> 8) as usize;
         let y = ((self.opcode & 0x00F0) >> 4) as usize;
+        let value = if self.quirks.shift_vy { self.v[y] } else { self.v[x] };
 
-        self.v[15] = self.v[x] & 1;
-        self.v[x] = self.v[y] >> 1;
+        self.v[x] = value >> 1;
+        self.v[15] = value & 1;
 
         self.pc += 2;
     }
@@ -392,12 +488,15 @@ impl CPU {
         self.pc += 2;
     }
 
+    // Vx = Vx << 1 (or Vy << 1)
+    // Set Vf to the bit shifted out
     fn oc_8xye(&mut self) {
         let x = ((self.opcode & 0x0F00) >> 8) as usize;
+        let y = ((self.opcode & 0x00F0) >> 4) as usize;
+        let value = if self.quirks.shift_vy { self.v[y] } else { self.v[x] };
 
-        // Set FLAG to MSB
-        self.v[15] = self.v[x] >> 7;
-        self.v[x] <<= 1;
+        self.v[x] = value << 1;
+        self.v[15] = value >> 7;
 
         self.pc += 2;
     }
@@ -422,11 +521,12 @@ impl CPU {
         self.pc += 2;
     }
 
-    // JMP to nnn + V0
+    // JMP to nnn + V0 (or nnn + Vx)
     fn oc_bnnn(&mut self) {
         let addr = (self.opcode & 0xFFF) as usize;
+        let x = if self.quirks.jump_vx { (addr >> 8) & 0xF } else { 0 };
 
-        self.pc = addr + self.v[0] as usize;
+        self.pc = addr + self.v[x] as usize;
     }
 
     // Generate number from 0-255 and bitwise-AND with kk
@@ -435,9 +535,7 @@ impl CPU {
         let x = ((self.opcode & 0x0F00) >> 8) as usize;
         let kk = (self.opcode & 0x00FF) as u8; 
 
-        let mut rng = rand::thread_rng();
-
-        self.v[x] = rng.gen_range(0, 255) & kk;
+        self.v[x] = self.rng.gen_range(0, 255) & kk;
 
         self.pc += 2;
     }
@@ -446,8 +544,9 @@ impl CPU {
     // If Vf is set, then there is a collision
     // Sprite is located at location I 
     fn oc_dxyn(&mut self) {
-        let x = self.v[((self.opcode & 0x0F00) >> 8) as usize] as usize;
-        let y = self.v[((self.opcode & 0x00F0) >> 4) as usize] as usize;
+        // The starting position always wraps, only the sprite itself is clipped
+        let x = self.v[((self.opcode & 0x0F00) >> 8) as usize] as usize % CHIP8_WIDTH;
+        let y = self.v[((self.opcode & 0x00F0) >> 4) as usize] as usize % CHIP8_HEIGHT;
         let height = (self.opcode & 0x000F) as usize;
 
         // Reset Vf flag
@@ -458,7 +557,15 @@ impl CPU {
         for row in 0..height {
             let pixel = self.memory[self.i + row];
 
+            if self.quirks.clip && y + row >= CHIP8_HEIGHT {
+                break;
+            }
+
             for col in 0..8 {
+                if self.quirks.clip && x + col >= CHIP8_WIDTH {
+                    break;
+                }
+
                 // Check if the current pixel is set 
                 if (pixel & (0x80 >> col)) != 0 {
                     // Check if current display pixel is set to 1
@@ -514,7 +621,8 @@ impl CPU {
         let x = ((self.opcode & 0x0F00) >> 8) as usize;
 
         self.key_wait = true;
-        self.key_wait_reg = x; 
+        self.key_wait_reg = x;
+        self.key_wait_key = None;
 
         self.pc += 2;
     }
@@ -573,6 +681,10 @@ impl CPU {
             self.memory[self.i + ind] = self.v[ind];
         }
 
+        if self.quirks.load_store_i {
+            self.i += x + 1;
+        }
+
         self.pc += 2;
     }
 
@@ -584,7 +696,183 @@ impl CPU {
             self.v[ind] = self.memory[self.i + ind];
         }
 
+        if self.quirks.load_store_i {
+            self.i += x + 1;
+        }
+
         self.pc += 2;
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::movie::{ Frame, KeyEvent };
+    use crate::timing::Ticks;
+
+    // A reader that fails partway through
+    struct Failing;
+
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk on fire"))
+        }
+    }
+
+    #[test]
+    fn empty_rom_is_rejected() {
+        let mut cpu = CPU::new();
+
+        assert!(matches!(cpu.load_bytes(&[], PROGRAM_START), Err(LoadError::Empty)));
+        assert!(matches!(cpu.load_reader(io::empty(), PROGRAM_START), Err(LoadError::Empty)));
+    }
+
+    #[test]
+    fn too_large_rom_reports_its_size() {
+        let mut cpu = CPU::new();
+        let rom = vec![0x12; MEMORY_SIZE];
+
+        match cpu.load_bytes(&rom, PROGRAM_START) {
+            Err(LoadError::TooLarge { size, max }) => assert_eq!((size, max), (MEMORY_SIZE, MEMORY_SIZE - PROGRAM_START)),
+            _ => panic!("expected TooLarge"),
+        }
+
+        match cpu.load_reader(&rom[..], PROGRAM_START) {
+            Err(LoadError::TooLarge { size, max }) => assert_eq!((size, max), (MEMORY_SIZE, MEMORY_SIZE - PROGRAM_START)),
+            _ => panic!("expected TooLarge"),
+        }
+    }
+
+    #[test]
+    fn rom_that_exactly_fits_loads() {
+        let mut cpu = CPU::new();
+        let rom = vec![0x12; MEMORY_SIZE - PROGRAM_START];
+
+        assert!(cpu.load_bytes(&rom, PROGRAM_START).is_ok());
+    }
+
+    #[test]
+    fn custom_address_sets_pc_and_memory() {
+        let mut cpu = CPU::new();
+
+        cpu.load_reader(&[0x60, 0x2A][..], 0x600).unwrap();
+
+        assert_eq!(cpu.pc, 0x600);
+        assert_eq!(&cpu.memory[0x600..0x602], &[0x60, 0x2A]);
+        assert_eq!(cpu.memory[PROGRAM_START], 0);
+        assert_eq!(&cpu.memory[..FONT_SET.len()], &FONT_SET[..]);
+
+        cpu.emulate_cycle([false; 16]);
+        assert_eq!(cpu.v[0], 0x2A);
+    }
+
+    #[test]
+    fn loading_resets_the_cpu() {
+        let mut cpu = CPU::new();
+
+        cpu.load_bytes(&[0x60, 0x2A], PROGRAM_START).unwrap();
+        cpu.emulate_cycle([false; 16]);
+        cpu.load_bytes(&[0x00, 0xE0], PROGRAM_START).unwrap();
+
+        assert_eq!(cpu.v[0], 0);
+        assert_eq!(cpu.pc, PROGRAM_START);
+    }
+
+    #[test]
+    fn reader_errors_are_passed_on() {
+        let mut cpu = CPU::new();
+
+        match cpu.load_reader(Failing, PROGRAM_START) {
+            Err(LoadError::Io(e)) => assert_eq!(e.to_string(), "disk on fire"),
+            _ => panic!("expected Io"),
+        }
+    }
+
+    #[test]
+    fn fetch_at_the_end_of_memory_wraps() {
+        let mut cpu = CPU::new();
+
+        cpu.load_bytes(&[0x60], MEMORY_SIZE - 1).unwrap();
+        cpu.memory[0] = 0x2A;
+        cpu.emulate_cycle([false; 16]);
+
+        assert_eq!(cpu.v[0], 0x2A);
+    }
+
+    // LD V0, K then loop
+    fn waiting_for_a_key() -> CPU {
+        let mut cpu = CPU::new();
+
+        cpu.load_bytes(&[0xF0, 0x0A, 0x12, 0x02], PROGRAM_START).unwrap();
+        cpu.emulate_cycle([false; 16]);
+        assert!(cpu.key_wait);
+
+        cpu
+    }
+
+    fn with_key(key: usize) -> [bool; 16] {
+        let mut keys = [false; 16];
+        keys[key] = true;
+        keys
+    }
+
+    #[test]
+    fn key_held_before_fx0a_is_ignored() {
+        let mut cpu = CPU::new();
+
+        cpu.load_bytes(&[0xF0, 0x0A, 0x12, 0x02], PROGRAM_START).unwrap();
+        cpu.emulate_cycle(with_key(5));
+        cpu.emulate_cycle(with_key(5));
+        cpu.emulate_cycle([false; 16]);
+
+        assert!(cpu.key_wait);
+        assert_eq!(cpu.key_wait_key, None);
+    }
+
+    #[test]
+    fn fx0a_completes_on_release_with_the_pressed_key() {
+        let mut cpu = waiting_for_a_key();
+
+        cpu.emulate_cycle(with_key(7));
+        cpu.emulate_cycle(with_key(7));
+        assert!(cpu.key_wait);
+
+        cpu.emulate_cycle([false; 16]);
+        assert!(!cpu.key_wait);
+        assert_eq!(cpu.v[0], 7);
+    }
+
+    #[test]
+    fn tap_within_a_frame_lands_on_separate_cycles() {
+        let mut queued = vec![
+            KeyEvent { key: 3, pressed: true, at: 0.5 },
+            KeyEvent { key: 3, pressed: false, at: 0.5 },
+        ];
+        let frame = Frame::new(Ticks { cycles: 10, timers: 1 }, &mut queued);
+        let cycles: Vec<u32> = frame.changes.iter().map(|c| c.cycle).collect();
+
+        assert_eq!(cycles, [5, 6]);
+        assert!(queued.is_empty());
+
+        // So the key is seen down for a cycle and the wait completes
+        let mut cpu = waiting_for_a_key();
+        frame.run(&mut cpu, &mut [false; 16]);
+
+        assert!(!cpu.key_wait);
+        assert_eq!(cpu.v[0], 3);
+    }
+
+    #[test]
+    fn events_that_dont_fit_wait_for_the_next_frame() {
+        let mut queued = vec![
+            KeyEvent { key: 3, pressed: true, at: 1.0 },
+            KeyEvent { key: 3, pressed: false, at: 1.0 },
+        ];
+        let frame = Frame::new(Ticks { cycles: 1, timers: 0 }, &mut queued);
+
+        assert_eq!(frame.changes.len(), 1);
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].at, 0.0);
+    }
+}
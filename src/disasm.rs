@@ -0,0 +1,200 @@
+// Turns opcodes back into assembly, in the mnemonics from Cowgod's
+// CHIP-8 technical reference. SUPER-CHIP and XO-CHIP opcodes are
+// recognised too so `info` can tell what a ROM was written for
+
+// The machine an instruction first appeared on, in order
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+}
+
+pub struct Instruction {
+    // The opcode's pattern, e.g. 8XY4, for counting which kinds are used
+    pub pattern: &'static str,
+    pub text: String,
+    pub platform: Platform,
+}
+
+// None for words that aren't an instruction, which are usually sprite data
+pub fn decode(opcode: u16) -> Option<Instruction> {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let kk = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    let chip8 = |pattern, text: String| Some(Instruction { pattern, text, platform: Platform::Chip8 });
+    let schip = |pattern, text: String| Some(Instruction { pattern, text, platform: Platform::SuperChip });
+    let xochip = |pattern, text: String| Some(Instruction { pattern, text, platform: Platform::XoChip });
+
+    match opcode >> 12 {
+        0x0 => match nnn {
+            0x0E0 => chip8("00E0", "CLS".to_string()),
+            0x0EE => chip8("00EE", "RET".to_string()),
+            0x0FB => schip("00FB", "SCR".to_string()),
+            0x0FC => schip("00FC", "SCL".to_string()),
+            0x0FD => schip("00FD", "EXIT".to_string()),
+            0x0FE => schip("00FE", "LOW".to_string()),
+            0x0FF => schip("00FF", "HIGH".to_string()),
+            _ if nnn & 0xFF0 == 0x0C0 => schip("00CN", format!("SCD {}", n)),
+            _ if nnn & 0xFF0 == 0x0D0 => xochip("00DN", format!("SCU {}", n)),
+            0x000 => None,
+            _ => chip8("0NNN", format!("SYS #{:03X}", nnn)),
+        },
+        0x1 => chip8("1NNN", format!("JP #{:03X}", nnn)),
+        0x2 => chip8("2NNN", format!("CALL #{:03X}", nnn)),
+        0x3 => chip8("3XKK", format!("SE V{:X}, #{:02X}", x, kk)),
+        0x4 => chip8("4XKK", format!("SNE V{:X}, #{:02X}", x, kk)),
+        0x5 => match n {
+            0x0 => chip8("5XY0", format!("SE V{:X}, V{:X}", x, y)),
+            0x2 => xochip("5XY2", format!("SAVE V{:X}-V{:X}", x, y)),
+            0x3 => xochip("5XY3", format!("LOAD V{:X}-V{:X}", x, y)),
+            _ => None,
+        },
+        0x6 => chip8("6XKK", format!("LD V{:X}, #{:02X}", x, kk)),
+        0x7 => chip8("7XKK", format!("ADD V{:X}, #{:02X}", x, kk)),
+        0x8 => {
+            let (pattern, name) = match n {
+                0x0 => ("8XY0", "LD"),
+                0x1 => ("8XY1", "OR"),
+                0x2 => ("8XY2", "AND"),
+                0x3 => ("8XY3", "XOR"),
+                0x4 => ("8XY4", "ADD"),
+                0x5 => ("8XY5", "SUB"),
+                0x6 => ("8XY6", "SHR"),
+                0x7 => ("8XY7", "SUBN"),
+                0xE => ("8XYE", "SHL"),
+                _ => return None,
+            };
+
+            chip8(pattern, format!("{} V{:X}, V{:X}", name, x, y))
+        }
+        0x9 if n == 0 => chip8("9XY0", format!("SNE V{:X}, V{:X}", x, y)),
+        0x9 => None,
+        0xA => chip8("ANNN", format!("LD I, #{:03X}", nnn)),
+        0xB => chip8("BNNN", format!("JP V0, #{:03X}", nnn)),
+        0xC => chip8("CXKK", format!("RND V{:X}, #{:02X}", x, kk)),
+        0xD if n == 0 => schip("DXY0", format!("DRW V{:X}, V{:X}, 0", x, y)),
+        0xD => chip8("DXYN", format!("DRW V{:X}, V{:X}, {}", x, y, n)),
+        0xE => match kk {
+            0x9E => chip8("EX9E", format!("SKP V{:X}", x)),
+            0xA1 => chip8("EXA1", format!("SKNP V{:X}", x)),
+            _ => None,
+        },
+        _ => match kk {
+            0x00 if x == 0 => xochip("F000", "LD I, long".to_string()),
+            0x01 => xochip("FN01", format!("PLANE {}", x)),
+            0x02 if x == 0 => xochip("F002", "AUDIO".to_string()),
+            0x07 => chip8("FX07", format!("LD V{:X}, DT", x)),
+            0x0A => chip8("FX0A", format!("LD V{:X}, K", x)),
+            0x15 => chip8("FX15", format!("LD DT, V{:X}", x)),
+            0x18 => chip8("FX18", format!("LD ST, V{:X}", x)),
+            0x1E => chip8("FX1E", format!("ADD I, V{:X}", x)),
+            0x29 => chip8("FX29", format!("LD F, V{:X}", x)),
+            0x30 => schip("FX30", format!("LD HF, V{:X}", x)),
+            0x33 => chip8("FX33", format!("LD B, V{:X}", x)),
+            0x3A => xochip("FX3A", format!("PITCH V{:X}", x)),
+            0x55 => chip8("FX55", format!("LD [I], V{:X}", x)),
+            0x65 => chip8("FX65", format!("LD V{:X}, [I]", x)),
+            0x75 => schip("FX75", format!("LD R, V{:X}", x)),
+            0x85 => schip("FX85", format!("LD V{:X}, R", x)),
+            _ => None,
+        },
+    }
+}
+
+// Which words of a ROM loaded at base can run, found by following jumps,
+// calls and skips from the first instruction. Sprites and other data are
+// left out, and so is code only reached through BNNN, which can't be followed
+pub fn reachable(rom: &[u8], base: usize) -> Vec<bool> {
+    let mut seen = vec![false; rom.len()];
+    let mut pending = vec![base];
+
+    let word = |addr: usize| -> Option<u16> {
+        let offset = addr.checked_sub(base)?;
+        let bytes = rom.get(offset..offset + 2)?;
+        Some((bytes[0] as u16) << 8 | bytes[1] as u16)
+    };
+
+    while let Some(addr) = pending.pop() {
+        let opcode = match word(addr) {
+            Some(opcode) if !seen[addr - base] => opcode,
+            _ => continue,
+        };
+
+        if decode(opcode).is_none() {
+            continue;
+        }
+
+        seen[addr - base] = true;
+        let nnn = (opcode & 0xFFF) as usize;
+
+        let skips = match opcode >> 12 {
+            0x3 | 0x4 | 0x9 | 0xE => true,
+            0x5 => opcode & 0xF == 0,
+            _ => false,
+        };
+
+        match opcode >> 12 {
+            // A skip over F000 NNNN skips all 4 bytes
+            _ if skips => {
+                let skipped = if word(addr + 2) == Some(0xF000) { 4 } else { 2 };
+                pending.extend([addr + 2, addr + 2 + skipped]);
+            }
+            // RET, EXIT and JP V0 go somewhere that isn't known here
+            0x0 if opcode == 0x00EE || opcode == 0x00FD => {}
+            0xB => {}
+            0x1 => pending.push(nnn),
+            0x2 => pending.extend([nnn, addr + 2]),
+            // The address after F000 is data
+            0xF if opcode == 0xF000 => pending.push(addr + 4),
+            _ => pending.push(addr + 2),
+        }
+    }
+
+    seen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_after_a_jump_is_not_reachable() {
+        // JP 204, then a DXY0 that's really sprite data, then a loop
+        let rom = [0x12, 0x04, 0xD1, 0x20, 0x12, 0x04];
+        let seen = reachable(&rom, 0x200);
+
+        assert!(seen[0] && !seen[2] && seen[4]);
+    }
+
+    #[test]
+    fn skips_and_calls_follow_both_ways() {
+        // CALL 208, SE V0 00, JP 200, JP 200, RET
+        let rom = [0x22, 0x08, 0x30, 0x00, 0x12, 0x00, 0x12, 0x00, 0x00, 0xEE];
+        let seen = reachable(&rom, 0x200);
+
+        assert_eq!(seen.iter().step_by(2).cloned().collect::<Vec<_>>(), [true, true, true, true, true]);
+    }
+
+    #[test]
+    fn code_can_be_loaded_elsewhere() {
+        // JP 602 at 600, then 00E0
+        let rom = [0x16, 0x02, 0x00, 0xE0];
+        let seen = reachable(&rom, 0x600);
+
+        assert!(seen[0] && seen[2]);
+    }
+}
@@ -1,8 +1,20 @@
-use sdl2;
+// The screen constants and font are shared by every frontend,
+// the Display window itself is only built with the sdl feature
+#[cfg(feature = "sdl")]
 use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::pixels::Color;
-use sdl2::video::Window;
+#[cfg(feature = "sdl")]
+use sdl2::render::{ Canvas, Texture, TextureCreator };
+#[cfg(feature = "sdl")]
+use sdl2::pixels::{ Color, PixelFormatEnum };
+#[cfg(feature = "sdl")]
+use sdl2::video::{ FullscreenType, Window, WindowContext };
+
+#[cfg(feature = "sdl")]
+use crate::config::Config;
+#[cfg(feature = "sdl")]
+use crate::effects::{ Effect, Effects, MAX_EFFECT_SCALE };
+#[cfg(feature = "sdl")]
+use crate::palette::Palette;
 
 // font set from multigesture.net
 pub const FONT_SET: [u8; 80] = [
@@ -24,58 +36,272 @@ pub const FONT_SET: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
-pub const SCREEN_MULTIPLY: usize = 20;
 pub const CHIP8_WIDTH: usize = 64;
 pub const CHIP8_HEIGHT: usize = 32;
-pub const MONITOR_HEIGHT: usize = CHIP8_HEIGHT * SCREEN_MULTIPLY;
-pub const MONITOR_WIDTH: usize = CHIP8_WIDTH * SCREEN_MULTIPLY;
 
+// Starting window size is the CHIP-8 screen times this
+pub const DEFAULT_SCALE: u32 = 10;
+pub const MAX_SCALE: u32 = 40;
+
+// How pixels that were just turned off are shown
+// CHIP-8 games erase and redraw sprites with XOR, which flickers badly,
+// these smooth it out without touching the CPU's framebuffer
+#[derive(Clone, Copy, PartialEq)]
+pub enum Persistence {
+    // Exactly what's in the framebuffer
+    Off,
+    // Pixels fade out over several frames like phosphor on a CRT
+    Fade,
+    // A pixel is lit if it was lit in this frame or the one before
+    Blend,
+}
+
+pub const PERSISTENCE_MODES: [(&str, Persistence); 3] = [
+    ("off", Persistence::Off),
+    ("fade", Persistence::Fade),
+    ("blend", Persistence::Blend),
+];
+
+// Fraction of its brightness a pixel keeps each frame when fading
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+#[cfg(feature = "sdl")]
 pub struct Display {
     canvas: Canvas<Window>,
-} 
+    creator: &'static TextureCreator<WindowContext>,
+    // Streaming texture the framebuffer is uploaded to,
+    // recreated whenever the framebuffer size changes
+    texture: Texture<'static>,
+    texture_size: (usize, usize),
+    // Size of the framebuffer being shown
+    frame_size: (usize, usize),
+    // The framebuffer in RGB, before effects
+    rgb: Vec<u8>,
+    // The framebuffer after effects
+    post: Vec<u8>,
+    effects: Effects,
+    palette: Palette,
+    // Fill the whole window instead of keeping the 2:1 aspect ratio
+    stretch: bool,
+    persistence: Persistence,
+    decay: f32,
+    // Brightness of every pixel as last shown, 0.0 to 1.0
+    levels: Vec<f32>,
+}
 
 // Drawing logic referenced from rust-sdl2 and starrhorne
+#[cfg(feature = "sdl")]
 impl Display {
-    pub fn new(ctx: &sdl2::Sdl) -> Display {
-        let video_subsystem = ctx.video().unwrap();
+    // Fails when there's no display to open a window on, e.g. over SSH
+    pub fn new(ctx: &sdl2::Sdl, config: &Config) -> Result<Display, String> {
+        let video_subsystem = ctx.video()?;
 
-        let window = video_subsystem
-        .window(
+        let mut builder = video_subsystem.window(
             "CHIP-8",
-            MONITOR_WIDTH as u32,
-            MONITOR_HEIGHT as u32,
-        )
-        .position_centered()
-        .build()
-        .unwrap();
+            CHIP8_WIDTH as u32 * config.scale,
+            CHIP8_HEIGHT as u32 * config.scale,
+        );
 
-        let mut canvas = window.into_canvas().build().unwrap();
+        builder.position_centered().resizable();
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        if config.fullscreen {
+            builder.fullscreen_desktop();
+        }
+
+        let window = builder.build().map_err(|e| e.to_string())?;
+        let palette = config.palette;
+
+        // With vsync, present() blocks until the next refresh
+        let canvas = if config.vsync {
+            window.into_canvas().present_vsync().build()
+        } else {
+            window.into_canvas().build()
+        };
+        let mut canvas = canvas.map_err(|e| e.to_string())?;
+
+        canvas.set_draw_color(to_color(palette.bg));
         canvas.clear();
         canvas.present();
 
-        Display {
+        // Textures borrow their creator, which would make Display borrow itself.
+        // The display lives as long as the program so the creator is simply
+        // leaked, which also keeps the renderer alive for the texture
+        let creator: &'static TextureCreator<WindowContext> = Box::leak(Box::new(canvas.texture_creator()));
+
+        let mut display = Display {
             canvas: canvas,
-        }
+            creator,
+            texture: create_texture(creator, CHIP8_WIDTH, CHIP8_HEIGHT),
+            texture_size: (CHIP8_WIDTH, CHIP8_HEIGHT),
+            frame_size: (CHIP8_WIDTH, CHIP8_HEIGHT),
+            rgb: Vec::new(),
+            post: Vec::new(),
+            effects: config.effects,
+            palette,
+            stretch: config.stretch,
+            persistence: config.persistence,
+            decay: config.decay,
+            levels: Vec::new(),
+        };
+
+        display.draw(&[[0; CHIP8_WIDTH]; CHIP8_HEIGHT]);
+        Ok(display)
+    }
+
+    // Takes on another game's look, keeping the window as it is
+    pub fn configure(&mut self, config: &Config) {
+        self.effects = config.effects;
+        self.palette = config.palette;
+        self.stretch = config.stretch;
+        self.persistence = config.persistence;
+        self.decay = config.decay;
+        self.levels.clear();
     }
 
     pub fn draw(&mut self, gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) {
-        for (y, row) in gfx.iter().enumerate() {
-            for (x, &col) in row.iter().enumerate() {
-                let x = (x as u32) * SCREEN_MULTIPLY as u32;
-                let y = (y as u32) * SCREEN_MULTIPLY as u32;
-
-                if col == 0 {
-                    self.canvas.set_draw_color(Color::RGB(0, 0, 0));
-                } else {
-                    self.canvas.set_draw_color(Color::RGB(255, 255, 255));
+        self.draw_frame(&gfx.concat(), CHIP8_WIDTH, CHIP8_HEIGHT);
+    }
+
+    // Uploads a framebuffer of any size, one byte per pixel,
+    // and scales it to the window with a single copy
+    pub fn draw_frame(&mut self, pixels: &[u8], width: usize, height: usize) {
+        self.frame_size = (width, height);
+
+        if self.levels.len() != pixels.len() {
+            self.levels = vec![0.0; pixels.len()];
+        }
+
+        self.rgb.clear();
+
+        for (&pixel, level) in pixels.iter().zip(self.levels.iter_mut()) {
+            let lit = if pixel != 0 { 1.0 } else { 0.0 };
+
+            let (r, g, b) = match self.persistence {
+                Persistence::Off => self.palette.color(pixel),
+                Persistence::Fade => {
+                    *level = if lit > 0.0 { lit } else { *level * self.decay };
+                    self.palette.mix(*level)
                 }
+                Persistence::Blend => {
+                    // level holds the previous frame here
+                    let shown = lit.max(*level);
+                    *level = lit;
+                    self.palette.mix(shown)
+                }
+            };
+
+            self.rgb.extend_from_slice(&[r, g, b]);
+        }
+
+        self.upload();
+        self.present();
+    }
+
+    // Puts the RGB framebuffer in the texture, running the effects first
+    fn upload(&mut self) {
+        let (width, height) = self.frame_size;
+
+        let (size, pixels) = if self.effects.any() {
+            let scale = self.effect_scale();
+            self.effects.apply(&self.rgb, width, height, scale, self.palette.bg, &mut self.post);
 
-                self.canvas.fill_rect(Rect::new(x as i32, y as i32, SCREEN_MULTIPLY as u32, SCREEN_MULTIPLY as u32)).expect("Draw failed");
-            }
+            ((width * scale, height * scale), &self.post)
+        } else {
+            ((width, height), &self.rgb)
+        };
+
+        if self.texture_size != size {
+            self.texture = create_texture(self.creator, size.0, size.1);
+            self.texture_size = size;
         }
 
+        self.texture.update(None, pixels, size.0 * 3).expect("Texture upload failed");
+    }
+
+    // Effects are rendered at the size they'll be shown at, up to a limit
+    fn effect_scale(&self) -> usize {
+        let view = self.viewport();
+        let scale = (view.width() as usize / self.frame_size.0).min(view.height() as usize / self.frame_size.1);
+
+        scale.clamp(1, MAX_EFFECT_SCALE)
+    }
+
+    // Whole number the framebuffer is currently scaled up by
+    pub fn scale(&self) -> usize {
+        let view = self.viewport();
+        (view.width() as usize / self.frame_size.0).max(1)
+    }
+
+    pub fn toggle_effect(&mut self, effect: Effect) {
+        self.effects.toggle(effect);
+        self.redraw();
+    }
+
+    // Whether the display changes from frame to frame on its own,
+    // in which case it has to be drawn every frame
+    pub fn animated(&self) -> bool {
+        self.persistence != Persistence::Off
+    }
+
+    // Draws the last frame again, e.g. after the window was resized
+    pub fn redraw(&mut self) {
+        self.upload();
+        self.present();
+    }
+
+    fn present(&mut self) {
+        let view = self.viewport();
+
+        // Clear the letterbox bars
+        self.canvas.set_draw_color(to_color(self.palette.bg));
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, view).expect("Draw failed");
         self.canvas.present();
     }
+
+    // Where the framebuffer goes in the window
+    // Scales by the largest whole number that fits and centres the result,
+    // or fills the window when stretching
+    fn viewport(&self) -> Rect {
+        let (w, h) = self.canvas.output_size().expect("Could not get window size");
+        let (fb_w, fb_h) = (self.frame_size.0 as u32, self.frame_size.1 as u32);
+
+        if self.stretch {
+            return Rect::new(0, 0, w.max(1), h.max(1));
+        }
+
+        let scale = (w / fb_w).min(h / fb_h).max(1);
+        let view_w = fb_w * scale;
+        let view_h = fb_h * scale;
+
+        Rect::new((w as i32 - view_w as i32) / 2, (h as i32 - view_h as i32) / 2, view_w, view_h)
+    }
+
+    pub fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+
+        let mode = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+
+        window.set_fullscreen(mode).expect("Could not change fullscreen mode");
+        self.redraw();
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).expect("Invalid window title");
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn to_color((r, g, b): (u8, u8, u8)) -> Color {
+    Color::RGB(r, g, b)
+}
+
+#[cfg(feature = "sdl")]
+fn create_texture(creator: &'static TextureCreator<WindowContext>, width: usize, height: usize) -> Texture<'static> {
+    creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .expect("Could not create texture")
 }
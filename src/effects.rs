@@ -0,0 +1,209 @@
+// CRT-style post-processing done entirely on the CPU,
+// so it works on the software renderer without a GPU.
+//
+// The coloured framebuffer is blown up by a whole number of output pixels
+// per CHIP-8 pixel and every output pixel is shaded by where it falls
+// inside its CHIP-8 pixel
+
+// Largest blow-up we do ourselves, SDL scales the rest
+#[cfg(feature = "sdl")]
+pub const MAX_EFFECT_SCALE: usize = 12;
+
+// How much scanlines and grid lines darken the picture
+#[cfg(feature = "sdl")]
+const SCANLINE_LEVEL: f32 = 0.55;
+#[cfg(feature = "sdl")]
+const GRID_LEVEL: f32 = 0.6;
+
+// Radius of a dot, as a fraction of the pixel
+#[cfg(feature = "sdl")]
+const DOT_RADIUS: f32 = 0.42;
+#[cfg(feature = "sdl")]
+const DOT_SOFTNESS: f32 = 0.08;
+
+#[cfg(feature = "sdl")]
+const BLOOM_STRENGTH: f32 = 0.45;
+
+// Screen curvature and how dark the corners get
+#[cfg(feature = "sdl")]
+const CURVATURE: f32 = 0.06;
+#[cfg(feature = "sdl")]
+const VIGNETTE_LEVEL: f32 = 0.45;
+
+#[cfg(feature = "sdl")]
+#[derive(Clone, Copy, PartialEq)]
+pub enum Effect {
+    Scanlines,
+    Grid,
+    Dots,
+    Bloom,
+    Vignette,
+}
+
+#[derive(Clone, Copy)]
+pub struct Effects {
+    pub scanlines: bool,
+    pub grid: bool,
+    pub dots: bool,
+    pub bloom: bool,
+    pub vignette: bool,
+}
+
+impl Effects {
+    pub fn new() -> Effects {
+        Effects {
+            scanlines: false,
+            grid: false,
+            dots: false,
+            bloom: false,
+            vignette: false,
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn any(&self) -> bool {
+        self.scanlines || self.grid || self.dots || self.bloom || self.vignette
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn toggle(&mut self, effect: Effect) {
+        let flag = match effect {
+            Effect::Scanlines => &mut self.scanlines,
+            Effect::Grid => &mut self.grid,
+            Effect::Dots => &mut self.dots,
+            Effect::Bloom => &mut self.bloom,
+            Effect::Vignette => &mut self.vignette,
+        };
+
+        *flag = !*flag;
+    }
+
+    // Renders rgb (width x height, 3 bytes per pixel) scaled up by scale into out
+    // bg is the colour unlit parts of a dot fall back to
+    #[cfg(feature = "sdl")]
+    pub fn apply(&self, rgb: &[u8], width: usize, height: usize, scale: usize, bg: (u8, u8, u8), out: &mut Vec<u8>) {
+        let out_w = width * scale;
+        let out_h = height * scale;
+        let bg = [bg.0 as f32, bg.1 as f32, bg.2 as f32];
+
+        let glow = if self.bloom { blur(rgb, width, height, bg) } else { Vec::new() };
+
+        out.clear();
+        out.reserve(out_w * out_h * 3);
+
+        for oy in 0..out_h {
+            for ox in 0..out_w {
+                // Position in CHIP-8 pixels, -1 to 1 across the screen for the curve
+                let mut u = (ox as f32 + 0.5) / out_w as f32 * 2.0 - 1.0;
+                let mut v = (oy as f32 + 0.5) / out_h as f32 * 2.0 - 1.0;
+                let mut level = 1.0;
+
+                if self.vignette {
+                    u *= 1.0 + CURVATURE * v * v;
+                    v *= 1.0 + CURVATURE * u * u;
+
+                    // Off the edge of the curved glass
+                    if u.abs() > 1.0 || v.abs() > 1.0 {
+                        out.extend_from_slice(&[0, 0, 0]);
+                        continue;
+                    }
+
+                    level *= 1.0 - VIGNETTE_LEVEL * ((u * u + v * v) / 2.0).powi(2);
+                }
+
+                let px = (u + 1.0) / 2.0 * width as f32;
+                let py = (v + 1.0) / 2.0 * height as f32;
+                let sx = (px as usize).min(width - 1);
+                let sy = (py as usize).min(height - 1);
+                let fx = px - sx as f32;
+                let fy = py - sy as f32;
+
+                let i = (sy * width + sx) * 3;
+                let mut color = [rgb[i] as f32, rgb[i + 1] as f32, rgb[i + 2] as f32];
+
+                if self.dots {
+                    let d = ((fx - 0.5).powi(2) + (fy - 0.5).powi(2)).sqrt();
+                    let inside = ((DOT_RADIUS + DOT_SOFTNESS - d) / DOT_SOFTNESS).clamp(0.0, 1.0);
+
+                    for c in 0..3 {
+                        color[c] = bg[c] + (color[c] - bg[c]) * inside;
+                    }
+                }
+
+                if self.scanlines && fy > 0.6 {
+                    level *= SCANLINE_LEVEL;
+                }
+
+                let line = 1.0 / scale as f32;
+
+                if self.grid && (fx < line || fy < line) {
+                    level *= GRID_LEVEL;
+                }
+
+                if self.bloom {
+                    let g = sample(&glow, width, height, px - 0.5, py - 0.5);
+
+                    for c in 0..3 {
+                        color[c] += g[c] * BLOOM_STRENGTH;
+                    }
+                }
+
+                for c in color.iter() {
+                    out.push((c * level).clamp(0.0, 255.0) as u8);
+                }
+            }
+        }
+    }
+}
+
+// 5x5 box blur of everything brighter than the background,
+// used for the glow around lit pixels
+#[cfg(feature = "sdl")]
+fn blur(rgb: &[u8], width: usize, height: usize, bg: [f32; 3]) -> Vec<[f32; 3]> {
+    let mut glow = vec![[0.0; 3]; width * height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 3];
+
+            for dy in -2i32..=2 {
+                for dx in -2i32..=2 {
+                    let nx = (x as i32 + dx).clamp(0, width as i32 - 1) as usize;
+                    let ny = (y as i32 + dy).clamp(0, height as i32 - 1) as usize;
+                    let i = (ny * width + nx) * 3;
+
+                    for c in 0..3 {
+                        sum[c] += (rgb[i + c] as f32 - bg[c]).max(0.0) / 25.0;
+                    }
+                }
+            }
+
+            glow[y * width + x] = sum;
+        }
+    }
+
+    glow
+}
+
+// Bilinear lookup so the glow doesn't come out blocky
+#[cfg(feature = "sdl")]
+fn sample(glow: &[[f32; 3]], width: usize, height: usize, x: f32, y: f32) -> [f32; 3] {
+    let x = x.clamp(0.0, width as f32 - 1.0);
+    let y = y.clamp(0.0, height as f32 - 1.0);
+    let x0 = x as usize;
+    let y0 = y as usize;
+    let x1 = (x0 + 1).min(width - 1);
+    let y1 = (y0 + 1).min(height - 1);
+    let tx = x - x0 as f32;
+    let ty = y - y0 as f32;
+
+    let mut out = [0.0; 3];
+
+    for c in 0..3 {
+        let top = glow[y0 * width + x0][c] * (1.0 - tx) + glow[y0 * width + x1][c] * tx;
+        let bottom = glow[y1 * width + x0][c] * (1.0 - tx) + glow[y1 * width + x1][c] * tx;
+        out[c] = top * (1.0 - ty) + bottom * ty;
+    }
+
+    out
+}
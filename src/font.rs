@@ -0,0 +1,105 @@
+// A tiny font for the emulator's own screens, like the ROM browser
+// FONT_SET only has hex digits, this has letters and some punctuation too.
+// Each glyph is 5 rows of 3 pixels, the low 3 bits of each byte, left to right
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// From one character to the next, including the gap
+pub const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+const GLYPHS: [(char, [u8; 5]); 58] = [
+    ('A', [2, 5, 7, 5, 5]),
+    ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]),
+    ('D', [6, 5, 5, 5, 6]),
+    ('E', [7, 4, 6, 4, 7]),
+    ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]),
+    ('H', [5, 5, 7, 5, 5]),
+    ('I', [7, 2, 2, 2, 7]),
+    ('J', [1, 1, 1, 5, 2]),
+    ('K', [5, 5, 6, 5, 5]),
+    ('L', [4, 4, 4, 4, 7]),
+    ('M', [5, 7, 7, 5, 5]),
+    ('N', [6, 5, 5, 5, 5]),
+    ('O', [2, 5, 5, 5, 2]),
+    ('P', [6, 5, 6, 4, 4]),
+    ('Q', [2, 5, 5, 6, 3]),
+    ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]),
+    ('T', [7, 2, 2, 2, 2]),
+    ('U', [5, 5, 5, 5, 7]),
+    ('V', [5, 5, 5, 5, 2]),
+    ('W', [5, 5, 7, 7, 5]),
+    ('X', [5, 5, 2, 5, 5]),
+    ('Y', [5, 5, 2, 2, 2]),
+    ('Z', [7, 1, 2, 4, 7]),
+    ('0', [7, 5, 5, 5, 7]),
+    ('1', [2, 6, 2, 2, 7]),
+    ('2', [6, 1, 2, 4, 7]),
+    ('3', [6, 1, 2, 1, 6]),
+    ('4', [5, 5, 7, 1, 1]),
+    ('5', [7, 4, 6, 1, 6]),
+    ('6', [3, 4, 7, 5, 7]),
+    ('7', [7, 1, 2, 2, 2]),
+    ('8', [7, 5, 7, 5, 7]),
+    ('9', [7, 5, 7, 1, 6]),
+    (' ', [0, 0, 0, 0, 0]),
+    ('.', [0, 0, 0, 0, 2]),
+    (',', [0, 0, 0, 2, 4]),
+    (':', [0, 2, 0, 2, 0]),
+    (';', [0, 2, 0, 2, 4]),
+    ('-', [0, 0, 7, 0, 0]),
+    ('+', [0, 2, 7, 2, 0]),
+    ('=', [0, 7, 0, 7, 0]),
+    ('*', [0, 5, 2, 5, 0]),
+    ('/', [1, 1, 2, 4, 4]),
+    ('_', [0, 0, 0, 0, 7]),
+    ('!', [2, 2, 2, 0, 2]),
+    ('?', [6, 1, 2, 0, 2]),
+    ('\'', [2, 2, 0, 0, 0]),
+    ('"', [5, 5, 0, 0, 0]),
+    ('(', [1, 2, 2, 2, 1]),
+    (')', [4, 2, 2, 2, 4]),
+    ('<', [1, 2, 4, 2, 1]),
+    ('>', [4, 2, 1, 2, 4]),
+    ('#', [5, 7, 5, 7, 5]),
+    ('&', [2, 5, 2, 5, 3]),
+    ('%', [5, 1, 2, 4, 5]),
+];
+
+// Lowercase is drawn as uppercase, anything the font lacks as ?
+fn glyph(c: char) -> [u8; 5] {
+    let find = |c| GLYPHS.iter().find(|&&(g, _)| g == c).map(|&(_, rows)| rows);
+
+    find(c.to_ascii_uppercase()).or_else(|| find('?')).unwrap_or([0; 5])
+}
+
+// Draws text into a framebuffer of one byte per pixel, setting the
+// glyphs' pixels to value and clipping at the edges
+pub fn draw_text(pixels: &mut [u8], width: usize, x: usize, y: usize, text: &str, value: u8) {
+    let height = pixels.len() / width;
+
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * ADVANCE;
+
+        if left >= width {
+            break;
+        }
+
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                let (px, py) = (left + col, y + row);
+
+                if bits & (4 >> col) != 0 && px < width && py < height {
+                    pixels[py * width + px] = value;
+                }
+            }
+        }
+    }
+}
+
+// Characters of text that fit across a width
+pub fn columns(width: usize) -> usize {
+    (width + 1) / ADVANCE
+}
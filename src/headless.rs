@@ -0,0 +1,73 @@
+// Plays a movie back with no window, input or sound device, as fast as the
+// machine allows. The screen and sound are only written to the video and
+// WAV files asked for, so gameplay can be rendered on a server and sound
+// behaviour can be regression tested
+use crate::capture::Capture;
+use crate::config::Config;
+use crate::cpu::CPU;
+use crate::movie::{ self, Movie };
+use crate::video::{ VideoFormat, VideoRecorder };
+
+pub fn run(rom: &str, config: &Config) -> Result<(), String> {
+    let path = config.play.as_ref()
+        .ok_or("the headless frontend plays a movie, give one with play=<file>")?;
+    let mut movie = Movie::load(path)?;
+
+    let mut cpu = CPU::new();
+    movie.header.apply(&mut cpu, rom)?;
+
+    if movie.header.rom_hash != movie::hash_bytes(&cpu.memory[0x200..]) {
+        eprintln!("chip8: warning: movie was recorded with a different ROM");
+    }
+
+    let mut video = match config.video {
+        Some(ref path) => {
+            let format = config.video_format.unwrap_or_else(|| VideoFormat::from_path(path));
+
+            Some(VideoRecorder::create(path, format, config.palette, config.video_scale)
+                .map_err(|e| format!("could not record video to {}: {}", path, e))?)
+        }
+        None => None,
+    };
+
+    // The same capture the SDL frontend uses, on the movie's timer ticks
+    let mut wav = match config.wav {
+        Some(ref path) => Some(Capture::create(path, config)
+            .map_err(|e| format!("could not record audio to {}: {}", path, e))?),
+        None => None,
+    };
+
+    let mut keys = [false; 16];
+
+    while let Some(frame) = movie.next_frame() {
+        let sound_events = frame.run(&mut cpu, &mut keys);
+
+        if let Some(ref mut w) = wav {
+            w.run(&sound_events, frame.ticks.timers).map_err(|e| format!("error writing audio: {}", e))?;
+        }
+
+        // One video frame per timer tick, so fast-forwarded frames
+        // play back at the speed the game ran
+        if let Some(ref mut v) = video {
+            for _ in 0..frame.ticks.timers {
+                v.frame(&cpu.gfx).map_err(|e| format!("error writing video: {}", e))?;
+            }
+        }
+    }
+
+    if let Some(v) = video {
+        v.finish().map_err(|e| format!("error writing video: {}", e))?;
+    }
+
+    if let Some(w) = wav {
+        w.finish().map_err(|e| format!("error writing audio: {}", e))?;
+    }
+
+    match movie.verify(&cpu.gfx) {
+        Some(true) => eprintln!("chip8: movie verified"),
+        Some(false) => return Err("movie mismatch, the screen differs from the recording".to_string()),
+        None => eprintln!("chip8: movie has no verification hash"),
+    }
+
+    Ok(())
+}
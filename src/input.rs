@@ -1,59 +1,369 @@
 use sdl2;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::event::{ Event, WindowEvent };
+use sdl2::keyboard::{ Keycode, Scancode };
+use sdl2::{ GameControllerSubsystem, TimerSubsystem };
+use sdl2::controller::{ Button, GameController };
+
+use crate::browser::MenuKey;
+use crate::keymap::{ Keymap, PadMap, PadInput, PLAYERS, AXIS_THRESHOLD };
+use crate::effects::Effect;
+use crate::movie::KeyEvent;
+
+// Emulator hotkeys, kept away from the keys games use
+pub const KEY_PAUSE: Keycode = Keycode::F5;
+pub const KEY_ADVANCE_FRAME: Keycode = Keycode::F6;
+pub const KEY_FAST_FORWARD_DOWN: Keycode = Keycode::F7;
+pub const KEY_FAST_FORWARD_UP: Keycode = Keycode::F8;
+pub const KEY_FULLSCREEN: Keycode = Keycode::F11;
+pub const KEY_SCREENSHOT: Keycode = Keycode::F12;
+pub const KEY_RECORD_VIDEO: Keycode = Keycode::F10;
+pub const KEY_MUTE: Keycode = Keycode::Backspace;
+pub const KEY_MENU: Keycode = Keycode::Escape;
+
+// Post-processing effect toggles
+pub const KEY_EFFECTS: [(Keycode, Effect); 5] = [
+    (Keycode::F1, Effect::Scanlines),
+    (Keycode::F2, Effect::Grid),
+    (Keycode::F3, Effect::Dots),
+    (Keycode::F4, Effect::Bloom),
+    (Keycode::F9, Effect::Vignette),
+];
+
+// Held rather than pressed
+pub const KEY_FAST_FORWARD: Scancode = Scancode::Tab;
+pub const KEY_SLOW_MOTION: Scancode = Scancode::Grave;
+
+// Every key the emulator keeps for itself, which games can't be bound to
+pub fn hotkeys() -> Vec<Scancode> {
+    let mut keycodes = vec![
+        KEY_PAUSE, KEY_ADVANCE_FRAME, KEY_FAST_FORWARD_DOWN, KEY_FAST_FORWARD_UP,
+        KEY_FULLSCREEN, KEY_SCREENSHOT, KEY_RECORD_VIDEO, KEY_MUTE, KEY_MENU,
+    ];
+    keycodes.extend(KEY_EFFECTS.iter().map(|&(k, _)| k));
+
+    // Matched up by name, which unlike the keyboard layout works before SDL is started
+    let mut scancodes: Vec<Scancode> = keycodes.iter()
+        .filter_map(|k| Scancode::from_name(&k.name()))
+        .collect();
+    scancodes.extend([KEY_FAST_FORWARD, KEY_SLOW_MOTION]);
+
+    scancodes
+}
+
+// One-shot actions triggered by hotkeys or window events
+pub enum Command {
+    TogglePause,
+    AdvanceFrame,
+    FastForwardUp,
+    FastForwardDown,
+    ToggleFullscreen,
+    ToggleEffect(Effect),
+    Screenshot,
+    ToggleVideo,
+    ToggleMute,
+    // Back to the ROM browser
+    Menu,
+    Redraw,
+}
+
+// Something that can hold a CHIP-8 key down
+#[derive(Copy, Clone, PartialEq)]
+enum Source {
+    Key(Scancode),
+    Pad(usize, PadInput),
+}
 
 pub struct Keypad {
     events: sdl2::EventPump,
+    timer: TimerSubsystem,
+    // SDL time of the previous poll, in ms
+    last_poll: u32,
+    // Every key and controller input currently held down
+    held: Vec<Source>,
+    // The CHIP-8 keys those add up to
+    state: [bool; 16],
+    commands: Vec<Command>,
+    keymap: Keymap,
+    // None when the platform has no controller support
+    controller_subsystem: Option<GameControllerSubsystem>,
+    // Each connected controller gets the first free player slot
+    players: [Option<GameController>; PLAYERS],
+    pads: [PadMap; PLAYERS],
 }
 
 impl Keypad {
-    pub fn new(ctx: &sdl2::Sdl)-> Keypad {
-        Keypad {
-            events: ctx.event_pump().unwrap()
+    pub fn new(ctx: &sdl2::Sdl, keymap: Keymap, pads: [PadMap; PLAYERS])-> Result<Keypad, String> {
+        // Controllers that are already plugged in show up as
+        // ControllerDeviceAdded events on the first poll
+        let controller_subsystem = match ctx.game_controller() {
+            Ok(subsystem) => Some(subsystem),
+            Err(e) => {
+                eprintln!("chip8: controllers unavailable: {}", e);
+                None
+            }
+        };
+
+        let mut timer = ctx.timer()?;
+        let last_poll = timer.ticks();
+
+        Ok(Keypad {
+            events: ctx.event_pump()?,
+            timer,
+            last_poll,
+            held: Vec::new(),
+            state: [false; 16],
+            commands: Vec::new(),
+            keymap,
+            controller_subsystem,
+            players: [None, None],
+            pads,
+        })
+    }
+
+    // Returns every CHIP-8 key press and release since the last poll, in order
+    // Events are queued by SDL between polls, so even a tap shorter
+    // than a frame comes through as a press followed by a release
+    pub fn poll(&mut self) -> Result<Vec<KeyEvent>, ()> {
+        let now = self.timer.ticks();
+        let window = now.saturating_sub(self.last_poll).max(1) as f64;
+        let start = self.last_poll;
+        self.last_poll = now;
+
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        let mut key_events = Vec::new();
+
+        for event in events {
+            // Where in the time since the last poll this happened, 0 to 1
+            let at = (timestamp(&event).unwrap_or(now).saturating_sub(start) as f64 / window).min(1.0);
+
+            match event {
+                Event::Quit { .. } => return Err(()),
+                Event::KeyDown { keycode, scancode, repeat: false, .. } => {
+                    let command = match keycode {
+                        Some(KEY_PAUSE) => Some(Command::TogglePause),
+                        Some(KEY_ADVANCE_FRAME) => Some(Command::AdvanceFrame),
+                        Some(KEY_FAST_FORWARD_UP) => Some(Command::FastForwardUp),
+                        Some(KEY_FAST_FORWARD_DOWN) => Some(Command::FastForwardDown),
+                        Some(KEY_FULLSCREEN) => Some(Command::ToggleFullscreen),
+                        Some(KEY_SCREENSHOT) => Some(Command::Screenshot),
+                        Some(KEY_RECORD_VIDEO) => Some(Command::ToggleVideo),
+                        Some(KEY_MUTE) => Some(Command::ToggleMute),
+                        Some(KEY_MENU) => Some(Command::Menu),
+                        Some(k) => KEY_EFFECTS.iter()
+                            .find(|&&(key, _)| key == k)
+                            .map(|&(_, effect)| Command::ToggleEffect(effect)),
+                        _ => None,
+                    };
+
+                    if let Some(c) = command {
+                        self.commands.push(c);
+                    }
+
+                    if let Some(sc) = scancode {
+                        self.hold(Source::Key(sc), true);
+                    }
+                }
+                Event::KeyUp { scancode: Some(sc), .. } => self.hold(Source::Key(sc), false),
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let Some(p) = self.player(which) {
+                        self.hold(Source::Pad(p, PadInput::Button(button)), true);
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let Some(p) = self.player(which) {
+                        self.hold(Source::Pad(p, PadInput::Button(button)), false);
+                    }
+                }
+                Event::ControllerAxisMotion { which, axis, value, .. } => {
+                    if let Some(p) = self.player(which) {
+                        self.hold(Source::Pad(p, PadInput::AxisPositive(axis)), value > AXIS_THRESHOLD);
+                        self.hold(Source::Pad(p, PadInput::AxisNegative(axis)), value < -AXIS_THRESHOLD);
+                    }
+                }
+                // The window contents are lost when it's resized or uncovered
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } => self.commands.push(Command::Redraw),
+                Event::ControllerDeviceAdded { which, .. } => self.connect(which as u32),
+                Event::ControllerDeviceRemoved { which, .. } => self.disconnect(which),
+                _ => {}
+            }
+
+            self.update(at, &mut key_events);
         }
+
+        Ok(key_events)
     }
 
-    pub fn poll(&mut self) -> Result<[bool; 16], ()> {
-        for event in self.events.poll_iter() {
-            if let Event::Quit { .. } = event {
-                return Err(());
+    // Navigation for the ROM browser, the CHIP-8 keypad isn't used there
+    // Key repeat is let through so holding an arrow scrolls
+    pub fn poll_menu(&mut self) -> Result<Vec<MenuKey>, ()> {
+        let events: Vec<Event> = self.events.poll_iter().collect();
+        let mut keys = Vec::new();
+
+        for event in events {
+            let key = match event {
+                Event::Quit { .. } => return Err(()),
+                Event::KeyDown { keycode: Some(keycode), .. } => match keycode {
+                    Keycode::Up => Some(MenuKey::Up),
+                    Keycode::Down => Some(MenuKey::Down),
+                    Keycode::PageUp => Some(MenuKey::PageUp),
+                    Keycode::PageDown => Some(MenuKey::PageDown),
+                    Keycode::Home => Some(MenuKey::First),
+                    Keycode::End => Some(MenuKey::Last),
+                    Keycode::Return | Keycode::KpEnter | Keycode::Space => Some(MenuKey::Select),
+                    Keycode::Escape => Some(MenuKey::Back),
+                    KEY_FULLSCREEN => {
+                        self.commands.push(Command::ToggleFullscreen);
+                        None
+                    }
+                    _ => None,
+                },
+                Event::ControllerButtonDown { button, .. } => match button {
+                    Button::DPadUp => Some(MenuKey::Up),
+                    Button::DPadDown => Some(MenuKey::Down),
+                    Button::LeftShoulder => Some(MenuKey::PageUp),
+                    Button::RightShoulder => Some(MenuKey::PageDown),
+                    Button::A | Button::Start => Some(MenuKey::Select),
+                    _ => None,
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } |
+                Event::Window { win_event: WindowEvent::Exposed, .. } => {
+                    self.commands.push(Command::Redraw);
+                    None
+                }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.connect(which as u32);
+                    None
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.disconnect(which);
+                    None
+                }
+                _ => None,
             };
+
+            keys.extend(key);
         }
 
-        let keys: Vec<Keycode> = self.events
-            .keyboard_state()
-            .pressed_scancodes()
-            .filter_map(Keycode::from_scancode)
-            .collect();
-
-        let mut chip8_keys = [false; 16];
-
-        for key in keys {
-            let index = match key {
-                Keycode::Num1 => Some(0x1),
-                Keycode::Num2 => Some(0x2),
-                Keycode::Num3 => Some(0x3),
-                Keycode::Num4 => Some(0xC),
-                Keycode::Q => Some(0x4),
-                Keycode::W => Some(0x5),
-                Keycode::E => Some(0x6),
-                Keycode::R => Some(0xD),
-                Keycode::A => Some(0x7),
-                Keycode::S => Some(0x8),
-                Keycode::D => Some(0x9),
-                Keycode::F => Some(0xE),
-                Keycode::Z => Some(0xA),
-                Keycode::X => Some(0x0),
-                Keycode::C => Some(0xB),
-                Keycode::V => Some(0xF),
-                _ => None,
+        // Nothing held in the menu carries over into the next game
+        self.held.clear();
+        self.state = [false; 16];
+        self.last_poll = self.timer.ticks();
+
+        Ok(keys)
+    }
+
+    // Switches to another game's bindings
+    pub fn set_bindings(&mut self, keymap: Keymap, pads: [PadMap; PLAYERS]) {
+        self.keymap = keymap;
+        self.pads = pads;
+    }
+
+    // Hotkeys pressed since the last call
+    pub fn commands(&mut self) -> Vec<Command> {
+        self.commands.drain(..).collect()
+    }
+
+    // The CHIP-8 keys held down as of the last poll
+    pub fn state(&self) -> [bool; 16] {
+        self.state
+    }
+
+    pub fn fast_forward_held(&self) -> bool {
+        self.events.keyboard_state().is_scancode_pressed(KEY_FAST_FORWARD)
+    }
+
+    pub fn slow_motion_held(&self) -> bool {
+        self.events.keyboard_state().is_scancode_pressed(KEY_SLOW_MOTION)
+    }
+
+    // Opens a newly plugged in controller, given its device index
+    fn connect(&mut self, index: u32) {
+        let subsystem = match self.controller_subsystem {
+            Some(ref s) => s,
+            None => return,
+        };
+
+        let slot = match self.players.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => return,
+        };
+
+        match subsystem.open(index) {
+            Ok(controller) => {
+                eprintln!("chip8: {} connected as player {}", controller.name(), slot + 1);
+                self.players[slot] = Some(controller);
+            }
+            Err(e) => eprintln!("chip8: could not open controller {}: {}", index, e),
+        }
+    }
+
+    // Frees the player slot of an unplugged controller, given its instance id
+    fn disconnect(&mut self, instance_id: i32) {
+        for (slot, player) in self.players.iter_mut().enumerate() {
+            let removed = player.as_ref().is_some_and(|c| c.instance_id() == instance_id);
+
+            if removed {
+                eprintln!("chip8: player {} controller disconnected", slot + 1);
+                *player = None;
+
+                // Let go of anything it was holding
+                self.held.retain(|&h| !matches!(h, Source::Pad(p, _) if p == slot));
+            }
+        }
+    }
+
+    // Player slot of a controller, given its instance id
+    fn player(&self, instance_id: i32) -> Option<usize> {
+        self.players
+            .iter()
+            .position(|p| p.as_ref().is_some_and(|c| c.instance_id() == instance_id))
+    }
+
+    fn hold(&mut self, source: Source, down: bool) {
+        let held = self.held.contains(&source);
+
+        if down && !held {
+            self.held.push(source);
+        } else if !down && held {
+            self.held.retain(|&h| h != source);
+        }
+    }
+
+    // Works out which CHIP-8 keys are down from everything that's held,
+    // and queues an event for every key that changed
+    fn update(&mut self, at: f64, key_events: &mut Vec<KeyEvent>) {
+        let mut state = [false; 16];
+
+        for &source in &self.held {
+            let key = match source {
+                Source::Key(sc) => self.keymap.lookup(sc),
+                Source::Pad(p, input) => self.pads[p].lookup(input),
             };
 
-            if let Some(i) = index {
-                chip8_keys[i] = true;
+            if let Some(k) = key {
+                state[k] = true;
+            }
+        }
+
+        for (key, (&now, &before)) in state.iter().zip(self.state.iter()).enumerate() {
+            if now != before {
+                key_events.push(KeyEvent { key, pressed: now, at });
             }
         }
 
-        Ok(chip8_keys)
+        self.state = state;
+    }
+}
+
+// SDL time an input event happened at, in ms
+fn timestamp(event: &Event) -> Option<u32> {
+    match *event {
+        Event::KeyDown { timestamp, .. } |
+        Event::KeyUp { timestamp, .. } |
+        Event::ControllerButtonDown { timestamp, .. } |
+        Event::ControllerButtonUp { timestamp, .. } |
+        Event::ControllerAxisMotion { timestamp, .. } => Some(timestamp),
+        _ => None,
     }
 }
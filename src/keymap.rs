@@ -0,0 +1,199 @@
+use sdl2::keyboard::Scancode;
+use sdl2::controller::{ Axis, Button };
+
+use crate::input;
+
+// Keyboard bindings for the 16 CHIP-8 keys
+// Bindings use scancodes, which name the physical position of a key rather
+// than what is printed on it, so AZERTY and Dvorak users get the same layout
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: Vec<(Scancode, usize)>,
+}
+
+// The COSMAC VIP hex pad on the left side of a keyboard
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+const COSMAC: [(Scancode, usize); 16] = [
+    (Scancode::Num1, 0x1), (Scancode::Num2, 0x2), (Scancode::Num3, 0x3), (Scancode::Num4, 0xC),
+    (Scancode::Q, 0x4), (Scancode::W, 0x5), (Scancode::E, 0x6), (Scancode::R, 0xD),
+    (Scancode::A, 0x7), (Scancode::S, 0x8), (Scancode::D, 0x9), (Scancode::F, 0xE),
+    (Scancode::Z, 0xA), (Scancode::X, 0x0), (Scancode::C, 0xB), (Scancode::V, 0xF),
+];
+
+// Each CHIP-8 key on the keyboard key with the same label
+const HEX: [(Scancode, usize); 16] = [
+    (Scancode::Num0, 0x0), (Scancode::Num1, 0x1), (Scancode::Num2, 0x2), (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0x4), (Scancode::Num5, 0x5), (Scancode::Num6, 0x6), (Scancode::Num7, 0x7),
+    (Scancode::Num8, 0x8), (Scancode::Num9, 0x9), (Scancode::A, 0xA), (Scancode::B, 0xB),
+    (Scancode::C, 0xC), (Scancode::D, 0xD), (Scancode::E, 0xE), (Scancode::F, 0xF),
+];
+
+// 0-9 on the numpad digits, A-F on the operator keys around them
+const NUMPAD: [(Scancode, usize); 16] = [
+    (Scancode::Kp0, 0x0), (Scancode::Kp1, 0x1), (Scancode::Kp2, 0x2), (Scancode::Kp3, 0x3),
+    (Scancode::Kp4, 0x4), (Scancode::Kp5, 0x5), (Scancode::Kp6, 0x6), (Scancode::Kp7, 0x7),
+    (Scancode::Kp8, 0x8), (Scancode::Kp9, 0x9), (Scancode::KpDivide, 0xA), (Scancode::KpMultiply, 0xB),
+    (Scancode::KpMinus, 0xC), (Scancode::KpPlus, 0xD), (Scancode::KpEnter, 0xE), (Scancode::KpPeriod, 0xF),
+];
+
+pub const PRESETS: [&str; 3] = ["cosmac", "hex", "numpad"];
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap::preset("cosmac").unwrap()
+    }
+
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let bindings = match name {
+            "cosmac" => COSMAC,
+            "hex" => HEX,
+            "numpad" => NUMPAD,
+            _ => return None,
+        };
+
+        Some(Keymap {
+            bindings: bindings.to_vec(),
+        })
+    }
+
+    // Replaces whatever is bound to key with the given scancodes
+    // A scancode can only drive one key, so it's taken away from any other key
+    pub fn bind(&mut self, key: usize, scancodes: &[Scancode]) {
+        self.bindings.retain(|&(sc, k)| k != key && !scancodes.contains(&sc));
+
+        for &sc in scancodes {
+            self.bindings.push((sc, key));
+        }
+    }
+
+    pub fn lookup(&self, scancode: Scancode) -> Option<usize> {
+        self.bindings
+            .iter()
+            .find(|&&(sc, _)| sc == scancode)
+            .map(|&(_, key)| key)
+    }
+}
+
+// Parses a binding such as `key.A = Z, Keypad 0`
+// into the CHIP-8 key and its scancodes
+pub fn parse_binding(key: &str, value: &str) -> Result<(usize, Vec<Scancode>), String> {
+    let index = parse_key(key)?;
+    let hotkeys = input::hotkeys();
+    let mut scancodes = Vec::new();
+
+    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        match Scancode::from_name(name) {
+            Some(sc) if hotkeys.contains(&sc) => return Err(format!("`{}` is an emulator hotkey", name)),
+            Some(sc) => scancodes.push(sc),
+            None => return Err(format!("unknown key name `{}`", name)),
+        }
+    }
+
+    Ok((index, scancodes))
+}
+
+// Parses a CHIP-8 key name, 0 to F
+fn parse_key(key: &str) -> Result<usize, String> {
+    usize::from_str_radix(key, 16)
+        .ok()
+        .filter(|&k| k < 16)
+        .ok_or_else(|| format!("`{}` is not a CHIP-8 key, expected 0-F", key))
+}
+
+// Number of controllers we hand out player slots to
+pub const PLAYERS: usize = 2;
+
+// How far a stick or trigger has to move before it counts as pressed
+pub const AXIS_THRESHOLD: i16 = 16000;
+
+// A controller button, or one direction of an axis
+#[derive(Copy, Clone, PartialEq)]
+pub enum PadInput {
+    Button(Button),
+    AxisPositive(Axis),
+    AxisNegative(Axis),
+}
+
+// Controller bindings for one player
+#[derive(Clone)]
+pub struct PadMap {
+    bindings: Vec<(PadInput, usize)>,
+}
+
+impl PadMap {
+    pub fn new() -> PadMap {
+        PadMap {
+            bindings: Vec::new(),
+        }
+    }
+
+    // Directions on 2 4 6 8 and fire on 5, which is what most games use
+    pub fn standard() -> PadMap {
+        PadMap {
+            bindings: vec![
+                (PadInput::Button(Button::DPadUp), 0x2),
+                (PadInput::Button(Button::DPadLeft), 0x4),
+                (PadInput::Button(Button::DPadRight), 0x6),
+                (PadInput::Button(Button::DPadDown), 0x8),
+                (PadInput::AxisNegative(Axis::LeftY), 0x2),
+                (PadInput::AxisNegative(Axis::LeftX), 0x4),
+                (PadInput::AxisPositive(Axis::LeftX), 0x6),
+                (PadInput::AxisPositive(Axis::LeftY), 0x8),
+                (PadInput::Button(Button::A), 0x5),
+            ],
+        }
+    }
+
+    // Replaces whatever is bound to key with the given inputs
+    pub fn bind(&mut self, key: usize, inputs: &[PadInput]) {
+        self.bindings.retain(|&(input, k)| k != key && !inputs.contains(&input));
+
+        for &input in inputs {
+            self.bindings.push((input, key));
+        }
+    }
+
+    pub fn lookup(&self, input: PadInput) -> Option<usize> {
+        self.bindings
+            .iter()
+            .find(|&&(i, _)| i == input)
+            .map(|&(_, key)| key)
+    }
+}
+
+// Parses a controller binding such as `pad1.C = dpup, lefty-`
+// Buttons and axes use SDL's controller mapping names,
+// axes need a + or - for the direction
+pub fn parse_pad_binding(key: &str, value: &str) -> Result<(usize, usize, Vec<PadInput>), String> {
+    let mut parts = key.splitn(2, '.');
+
+    let name = parts.next().unwrap_or("");
+
+    let player = name.parse::<usize>()
+        .ok()
+        .filter(|p| (1..=PLAYERS).contains(p))
+        .ok_or_else(|| format!("`pad{}` is not a player, expected pad1 to pad{}", name, PLAYERS))?;
+
+    let index = parse_key(parts.next().unwrap_or(""))?;
+    let mut inputs = Vec::new();
+
+    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        let input = if let Some(axis) = name.strip_suffix('+') {
+            Axis::from_string(axis).map(PadInput::AxisPositive)
+        } else if let Some(axis) = name.strip_suffix('-') {
+            Axis::from_string(axis).map(PadInput::AxisNegative)
+        } else {
+            Button::from_string(name).map(PadInput::Button)
+        };
+
+        match input {
+            Some(i) => inputs.push(i),
+            None => return Err(format!("unknown controller input `{}`", name)),
+        }
+    }
+
+    Ok((player - 1, index, inputs))
+}
@@ -1,54 +1,664 @@
 mod cpu;
+#[cfg(feature = "sdl")]
 mod input;
+#[cfg(feature = "sdl")]
 mod audio;
 mod display;
+mod timing;
+mod config;
+#[cfg(feature = "sdl")]
+mod keymap;
+mod movie;
+mod palette;
+mod effects;
+mod screenshot;
+mod video;
+mod tone;
+mod beeper;
+mod capture;
+mod wav;
+mod headless;
+mod quirks;
+mod cli;
+mod commands;
+mod disasm;
+mod terminal;
+mod romdb;
+#[cfg(feature = "sdl")]
+mod font;
+#[cfg(feature = "sdl")]
+mod browser;
+mod watch;
 
-use std::thread;
-use std::time::Duration;
+use std::process;
+#[cfg(feature = "sdl")]
+use std::path::PathBuf;
 
+use cli::Invocation;
+use config::{ Config, Frontend };
+
+#[cfg(feature = "sdl")]
 use cpu::CPU;
+#[cfg(feature = "sdl")]
 use audio::Audio;
+#[cfg(feature = "sdl")]
 use display::Display;
-use input::Keypad;
+#[cfg(feature = "sdl")]
+use input::{ Keypad, Command };
+#[cfg(feature = "sdl")]
+use timing::{ Clock, FrameScheduler, FRAME_HZ, FAST_FORWARD_STEPS, UNTHROTTLED };
+#[cfg(feature = "sdl")]
+use movie::{ Frame, Header, Movie, Recorder, SoundEvent };
+#[cfg(feature = "sdl")]
+use video::{ VideoFormat, VideoRecorder };
+#[cfg(feature = "sdl")]
+use browser::{ Action, Browser, MENU_WIDTH, MENU_HEIGHT };
+#[cfg(feature = "sdl")]
+use watch::{ Watch, Watcher };
 
 use std::env;
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let invocation = match cli::parse(&args) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("chip8: {}", e);
+            eprintln!("Try `chip8 --help` for more information.");
+            process::exit(2);
+        }
+    };
+
+    let result = match invocation {
+        Invocation::Run { rom, options } => {
+            run(rom.as_deref(), &options);
+            Ok(())
+        }
+        Invocation::Disasm { rom, options } => {
+            Config::load(&rom, &options).and_then(|config| commands::disasm(&rom, &config))
+        }
+        Invocation::Info { rom, options } => {
+            Config::load(&rom, &options).and_then(|config| commands::info(&rom, &config))
+        }
+        Invocation::Bench { rom, options, frames } => {
+            Config::load(&rom, &options).and_then(|config| commands::bench(&rom, &config, frames))
+        }
+        Invocation::Test { roms, options, frames, bless } => {
+            match commands::test(&roms, &options, frames, bless) {
+                Ok(true) => Ok(()),
+                Ok(false) => process::exit(1),
+                Err(e) => Err(e),
+            }
+        }
+        Invocation::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+        Invocation::Version => {
+            println!("chip8 {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("chip8: {}", e);
+        process::exit(1);
+    }
+}
+
+// Plays the ROM with whichever frontend the settings ask for,
+// or lets the player pick one when there isn't one
+fn run(rom: Option<&str>, options: &[String]) {
+    let loaded = match rom {
+        Some(rom) => Config::load(rom, options),
+        None => Config::load_without_rom(options),
+    };
+
+    let config = match loaded {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("chip8: {}", e);
+            process::exit(2);
+        }
+    };
+
+    // Only the SDL frontend has a ROM browser
+    let rom = match (rom, config.frontend) {
+        (Some(rom), _) => rom,
+        #[cfg(feature = "sdl")]
+        (None, Frontend::Sdl) => {
+            if config.play.is_some() || config.record.is_some() || config.video.is_some() || config.wav.is_some() {
+                eprintln!("chip8: play, record, video and wav need a ROM");
+                process::exit(2);
+            }
+
+            return run_sdl(&config, None, options);
+        }
+        (None, _) => {
+            eprintln!("chip8: no ROM given");
+            eprintln!("Try `chip8 --help` for more information.");
+            process::exit(2);
+        }
+    };
+
+    match config.frontend {
+        Frontend::Terminal => {
+            if let Err(e) = terminal::run(rom, &config) {
+                eprintln!("chip8: {}", e);
+                process::exit(1);
+            }
+        }
+        Frontend::Headless => {
+            if let Err(e) = headless::run(rom, &config) {
+                eprintln!("chip8: {}", e);
+                process::exit(1);
+            }
+        }
+        #[cfg(feature = "sdl")]
+        Frontend::Sdl => run_sdl(&config, Some(rom), options),
+        #[cfg(not(feature = "sdl"))]
+        Frontend::Sdl => unreachable!(),
+    }
+}
+
+// How a game ended
+#[cfg(feature = "sdl")]
+#[derive(PartialEq)]
+enum Exit {
+    Quit,
+    Menu,
+}
+
+// Opens the SDL window and plays the ROM, then the ROMs picked in the browser.
+// Every game gets its own settings, the window and input stay open throughout
+#[cfg(feature = "sdl")]
+fn run_sdl(config: &Config, rom: Option<&str>, options: &[String]) {
+    // Load the game into memory before opening any windows
+    let first = rom.map(|rom| {
+        load_rom(rom, config).unwrap_or_else(|e| {
+            eprintln!("chip8: {}", e);
+            process::exit(1);
+        })
+    });
+
+    let opened = sdl2::init().and_then(|sdl_context| {
+        let disp = Display::new(&sdl_context, config)?;
+        let keypad = Keypad::new(&sdl_context, config.keymap.clone(), config.pads.clone())?;
+
+        Ok((sdl_context, disp, keypad))
+    });
+
+    let (sdl_context, mut disp, mut keypad) = opened.unwrap_or_else(|e| {
+        eprintln!("chip8: could not open a window: {}", e);
+        eprintln!("Without a display, try `--frontend terminal`.");
+        process::exit(1);
+    });
+
+    if let (Some(rom), Some(cpu)) = (rom, first) {
+        if play(&sdl_context, config, rom, cpu, &mut disp, &mut keypad) == Exit::Quit {
+            return;
+        }
+    }
+
+    let mut browser = Browser::new(&config.rom_dir);
+
+    while let Some(path) = browse(&mut browser, &mut disp, &mut keypad, config) {
+        let rom = path.to_string_lossy();
+
+        // Set up the same as if it was given on the command line
+        let loaded = Config::load(&rom, options)
+            .and_then(|game_config| load_rom(&rom, &game_config).map(|cpu| (game_config, cpu)));
+
+        let (mut game_config, cpu) = match loaded {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("chip8: {}", e);
+                continue;
+            }
+        };
+
+        // Movies, videos and WAV files belong to the ROM given on the command line,
+        // another game would play the wrong movie or write over the files
+        game_config.play = None;
+        game_config.record = None;
+        game_config.video = None;
+        game_config.wav = None;
+
+        if play(&sdl_context, &game_config, &rom, cpu, &mut disp, &mut keypad) == Exit::Quit {
+            return;
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn load_rom(rom: &str, config: &Config) -> Result<CPU, String> {
     let mut cpu = CPU::new();
-    let sdl_context = sdl2::init().unwrap();
 
-    let mut disp = Display::new(&sdl_context);
-    let sound = Audio::new(&sdl_context);
-    let mut keypad = Keypad::new(&sdl_context);
+    cpu.load_path(rom, config.load_address).map_err(|e| format!("{}: {}", rom, e))?;
+    cpu.quirks = config.quirks;
+
+    Ok(cpu)
+}
+
+// Shows the ROM browser until a ROM is picked, None when the player quits
+#[cfg(feature = "sdl")]
+fn browse(browser: &mut Browser, disp: &mut Display, keypad: &mut Keypad, config: &Config) -> Option<PathBuf> {
+    let mut frames = FrameScheduler::new(config.vsync);
+
+    // Picks up ROMs added since it was last shown
+    browser.refresh();
+
+    disp.configure(config);
+    disp.set_title(&format!("CHIP-8 - {} (Enter to play, Esc to quit)", browser.dir));
+    disp.draw_frame(&browser.render(), MENU_WIDTH, MENU_HEIGHT);
+
+    while let Ok(keys) = keypad.poll_menu() {
+        let mut changed = false;
+
+        for key in keys {
+            match browser.handle(key) {
+                Action::Launch(path) => return Some(path),
+                Action::Quit => return None,
+                Action::Redraw => changed = true,
+            }
+        }
+
+        for command in keypad.commands() {
+            match command {
+                Command::ToggleFullscreen => disp.toggle_fullscreen(),
+                Command::Redraw => disp.redraw(),
+                _ => {}
+            }
+        }
+
+        if changed || config.vsync || disp.animated() {
+            disp.draw_frame(&browser.render(), MENU_WIDTH, MENU_HEIGHT);
+        }
+
+        frames.wait();
+    }
+
+    None
+}
+
+// Runs one game until the window is closed or the player goes back to the menu
+#[cfg(feature = "sdl")]
+fn play(sdl_context: &sdl2::Sdl, config: &Config, rom: &str, mut cpu: CPU, disp: &mut Display, keypad: &mut Keypad) -> Exit {
+    let mut sound = Audio::new(sdl_context, config);
+
+    keypad.set_bindings(config.keymap.clone(), config.pads.clone());
+    disp.configure(config);
+    disp.draw(&cpu.gfx);
 
-    let args: Vec<String> = env::args().collect();
+    let mut movie = config.play.as_ref().map(|path| {
+        Movie::load(path).unwrap_or_else(|e| {
+            eprintln!("chip8: {}", e);
+            process::exit(1);
+        })
+    });
 
-    // Initialize the CPU and load the game into memory
-    cpu.initialize(args[1].to_string());
+    // A movie brings its own seed so CXKK rolls the same numbers,
+    // and its own quirks and load address so every opcode does the same thing
+    let (seed, load_address) = match movie {
+        Some(ref m) => {
+            if let Err(e) = m.header.apply(&mut cpu, rom) {
+                eprintln!("chip8: {}", e);
+                process::exit(1);
+            }
 
-    while let Ok(kp) = keypad.poll() {
+            (m.header.seed, m.header.load_address)
+        }
+        None => {
+            let seed = config.seed.unwrap_or_else(rand::random);
+            cpu.seed(seed);
 
-        for _ in 1..=9 {
-            cpu.emulate_cycle(kp);
+            (seed, config.load_address)
+        }
+    };
+
+    let rom_hash = movie::hash_bytes(&cpu.memory[0x200..]);
+
+    if let Some(ref m) = movie {
+        if m.header.rom_hash != rom_hash {
+            eprintln!("chip8: warning: movie was recorded with a different ROM");
+        }
+    }
+
+    // What the game was started with, for recording and reloading
+    let mut header = Header { rom_hash, seed, clock_hz: config.clock_hz, quirks: cpu.quirks, load_address };
+
+    let mut recorder = config.record.as_ref().map(|path| {
+        Recorder::create(path, &header).unwrap_or_else(|e| {
+            eprintln!("chip8: could not create movie {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+
+    let mut video = config.video.as_ref().map(|path| {
+        let format = config.video_format.unwrap_or_else(|| VideoFormat::from_path(path));
+
+        VideoRecorder::create(path, format, config.palette, config.video_scale).unwrap_or_else(|e| {
+            eprintln!("chip8: could not record video to {}: {}", path, e);
+            process::exit(1);
+        })
+    });
+
+    let mut clock = Clock::new(config.clock_hz);
+    let mut frames = FrameScheduler::new(config.vsync);
+    let mut frames_since_report = 0;
+
+    let mut paused = config.start_paused;
+    let mut fast_forward = config.fast_forward;
+    let mut title = String::new();
+    let name = match config.title {
+        Some(ref rom_title) => format!("CHIP-8 - {}", rom_title),
+        None => "CHIP-8".to_string(),
+    };
+    let mut movie_frames = 0.0;
+
+    // Keypad state the CPU sees, and key events waiting for a cycle to land on
+    let mut keys = [false; 16];
+    let mut queued = Vec::new();
+
+    // Closing the window quits, so does a movie running out
+    let mut exit = Exit::Quit;
+
+    let mut watcher = if config.watch != Watch::Off { Some(Watcher::new(rom)) } else { None };
+
+    // Every frame since the game started, to replay when the ROM is reloaded
+    let mut history = Vec::new();
+
+    // Sound changes to queue at the end of the pass
+    let mut sound_events = Vec::new();
+
+    while let Ok(events) = keypad.poll() {
+        queued.extend(events);
+
+        let mut advance_frame = false;
+
+        for command in keypad.commands() {
+            match command {
+                Command::TogglePause => paused = !paused,
+                Command::AdvanceFrame => advance_frame = paused,
+                Command::FastForwardUp => fast_forward = step_fast_forward(fast_forward, 1),
+                Command::FastForwardDown => fast_forward = step_fast_forward(fast_forward, -1),
+                Command::ToggleFullscreen => disp.toggle_fullscreen(),
+                Command::ToggleEffect(effect) => disp.toggle_effect(effect),
+                Command::Screenshot => {
+                    let scale = if config.screenshot_native { 1 } else { disp.scale() };
+
+                    match screenshot::save(&cpu.gfx, &config.palette, scale, &config.capture_dir, rom) {
+                        Ok(path) => eprintln!("chip8: saved screenshot {}", path.display()),
+                        Err(e) => eprintln!("chip8: could not save screenshot: {}", e),
+                    }
+                }
+                Command::ToggleVideo => video = toggle_video(video, config, rom),
+                Command::ToggleMute => {
+                    let muted = sound.toggle_mute();
+                    eprintln!("chip8: sound {}", if muted { "muted" } else { "on" });
+                }
+                Command::Menu => exit = Exit::Menu,
+                Command::Redraw => disp.redraw(),
+            }
+        }
+
+        if exit == Exit::Menu {
+            break;
+        }
+
+        if watcher.as_mut().is_some_and(|w| w.changed()) {
+            if config.watch == Watch::Restart {
+                history.clear();
+
+                if let Some(ref mut m) = movie {
+                    m.rewind();
+                }
+            }
+
+            match watch::reload(rom, &header, &history, &mut keys) {
+                Ok(reloaded) => {
+                    cpu = reloaded;
+                    eprintln!("chip8: reloaded {}", rom);
+
+                    // The new ROM's sound might not be in the state the old one left it in
+                    sound_events.push(SoundEvent { on: cpu.sound_timer > 0, at: 0.0 });
+                    watch::resync(&keys, &keypad.state(), &mut queued);
+
+                    // The movie starts over for the new ROM, from the frames it was replayed through
+                    header.rom_hash = movie::hash_bytes(&cpu.memory[0x200..]);
+
+                    if let Some(path) = config.record.as_ref().filter(|_| recorder.is_some()) {
+                        recorder = restart_movie(path, &header, &history);
+                    }
+                }
+                Err(e) => eprintln!("chip8: could not reload: {}", e),
+            }
+        }
+
+        let fast = keypad.fast_forward_held();
+        let slow = keypad.slow_motion_held();
+        let unthrottled = !paused && fast && fast_forward == UNTHROTTLED;
+
+        // How fast emulated time runs this pass.
+        // The speed scales emulated time as a whole, so the timers
+        // and sound keep in step with the CPU
+        let rate = if paused {
+            0.0
+        } else if fast && !unthrottled {
+            fast_forward as f64
+        } else if slow {
+            config.slow_motion
+        } else {
+            1.0
+        };
+
+        // Single frames (frame advance, unthrottled) always run exactly one frame's worth
+        let single_frame = advance_frame || unthrottled;
+        let mut due = Vec::new();
+
+        match movie {
+            // A movie already knows what every frame ran,
+            // it's played back one recorded frame per frame at normal speed
+            Some(ref mut m) => {
+                queued.clear();
+                movie_frames = if single_frame { 1.0 } else { movie_frames + rate };
+
+                while movie_frames >= 1.0 {
+                    movie_frames -= 1.0;
+
+                    match m.next_frame() {
+                        Some(frame) => due.push(frame),
+                        None => break,
+                    }
+                }
+            }
+            // Run however many cycles and timer ticks are due since the last pass,
+            // based on real time so the speed doesn't depend on how long
+            // drawing and polling took
+            None => {
+                let ticks = if single_frame {
+                    clock.advance(0.0);
+                    clock.advance_by(Clock::frame_time())
+                } else {
+                    clock.advance(rate)
+                };
+
+                due.push(Frame::new(ticks, &mut queued));
+            }
+        }
+
+        // Sound changes are spread over one frame of audio however many
+        // frames ran, so fast-forward plays them sped up rather than late
+        for (i, frame) in due.iter().enumerate() {
+            // A full disk stops the recording rather than the game
+            if let Some(Err(e)) = recorder.as_mut().map(|r| r.frame(frame)) {
+                eprintln!("chip8: error writing movie, recording stopped: {}", e);
+                recorder = None;
+            }
+
+            for mut event in frame.run(&mut cpu, &mut keys) {
+                event.at = (i as f64 + event.at) / due.len() as f64;
+                sound_events.push(event);
+            }
+        }
+
+        if config.watch == Watch::Replay {
+            history.extend(due.iter().cloned());
+        }
+
+        // Captures go by emulated timer ticks, the game's 60 Hz, so pausing
+        // doesn't leave a still in them and speeding up doesn't slow them down
+        let ticks = due.iter().map(|f| f.ticks.timers).sum::<u32>();
+
+        sound.set_paused(paused);
+        sound.queue(&sound_events, ticks);
+        sound_events.clear();
+
+        // One video frame per tick
+        // A write error, like the encoder on the other end of a pipe going away,
+        // stops the recording rather than the game
+        let written = video.as_mut().map(|v| (0..ticks).try_for_each(|_| v.frame(&cpu.gfx)));
+
+        if let Some(Err(e)) = written {
+            eprintln!("chip8: error writing video, recording stopped: {}", e);
+            video = None;
+        }
+
+        if movie.as_ref().is_some_and(|m| m.finished()) {
+            break;
         }
 
-        // Wait for some time
-        thread::sleep(Duration::from_millis(16));
-       
         // Handle drawing if there is a need
-        if cpu.draw_flag {
+        // with vsync we present every frame since that's what paces the loop,
+        // and fading pixels change every frame too
+        if cpu.draw_flag || config.vsync || disp.animated() {
             disp.draw(&cpu.gfx);
             cpu.draw_flag = false;
         }
 
-        if cpu.sound_timer > 0 {
-            sound.play();
+        let status = if paused {
+            format!("{} [paused]", name)
+        } else if unthrottled {
+            format!("{} [fast-forward max]", name)
+        } else if fast {
+            format!("{} [fast-forward {}x]", name, fast_forward)
+        } else if slow {
+            format!("{} [slow motion {}x]", name, config.slow_motion)
         } else {
-            sound.stop();
+            name.clone()
+        };
+
+        if status != title {
+            disp.set_title(&status);
+            title = status;
         }
 
-        // Decrement the timer after 60hz is up
-        // this happens after 60hz since there are 9 cycles per timer decrement
-        cpu.decrement_timers();
+        // Wait out the rest of the frame
+        // Unthrottled runs back to back, one emulated frame per pass
+        if unthrottled {
+            frames.reset();
+            continue;
+        }
+
+        frames.wait();
+        frames_since_report += 1;
+
+        // Report dropped frames every few seconds
+        if frames_since_report == FRAME_HZ as u32 * 5 {
+            frames_since_report = 0;
+
+            let dropped = frames.take_dropped();
+
+            if dropped > 0 {
+                eprintln!("chip8: dropped {} frames in the last 5s", dropped);
+            }
+        }
+    }
+
+    if let Some(Err(e)) = recorder.map(|r| r.finish(&cpu.gfx)) {
+        eprintln!("chip8: could not finish movie: {}", e);
+    }
+
+    if let Some(Err(e)) = video.map(|v| v.finish()) {
+        eprintln!("chip8: could not finish video: {}", e);
+    }
+
+    if let Err(e) = sound.finish() {
+        eprintln!("chip8: could not finish audio: {}", e);
+    }
+
+    // Check the playback ended on the same screen as the recording
+    if let Some(m) = movie {
+        if !m.finished() {
+            return exit;
+        }
+
+        match m.verify(&cpu.gfx) {
+            Some(true) => eprintln!("chip8: movie verified"),
+            Some(false) => {
+                eprintln!("chip8: movie mismatch, the screen differs from the recording");
+                process::exit(1);
+            }
+            None => eprintln!("chip8: movie has no verification hash"),
+        }
+    }
+
+    exit
+}
+
+// Moves to the next or previous fast-forward multiplier
+#[cfg(feature = "sdl")]
+fn step_fast_forward(current: u32, step: isize) -> u32 {
+    let index = FAST_FORWARD_STEPS.iter().position(|&x| x == current).unwrap_or(0) as isize;
+    let last = FAST_FORWARD_STEPS.len() as isize - 1;
+
+    FAST_FORWARD_STEPS[(index + step).max(0).min(last) as usize]
+}
+
+// Records a movie over again from the start, None if it can't be
+#[cfg(feature = "sdl")]
+fn restart_movie(path: &str, header: &Header, history: &[Frame]) -> Option<Recorder> {
+    let result = Recorder::create(path, header).and_then(|mut r| {
+        for frame in history {
+            r.frame(frame)?;
+        }
+
+        Ok(r)
+    });
+
+    match result {
+        Ok(r) => Some(r),
+        Err(e) => {
+            eprintln!("chip8: stopped recording, could not restart movie {}: {}", path, e);
+            None
+        }
+    }
+}
+
+// Starts a new recording in the capture directory, or finishes the current one
+#[cfg(feature = "sdl")]
+fn toggle_video(video: Option<VideoRecorder>, config: &Config, rom: &str) -> Option<VideoRecorder> {
+    if let Some(v) = video {
+        match v.finish() {
+            Ok(()) => eprintln!("chip8: video recording stopped"),
+            Err(e) => eprintln!("chip8: could not finish video: {}", e),
+        }
+
+        return None;
+    }
+
+    let format = config.video_format.unwrap_or(VideoFormat::Gif);
+    let path = screenshot::unique_path(&config.capture_dir, rom, format.extension());
+
+    match VideoRecorder::create(&path.to_string_lossy(), format, config.palette, config.video_scale) {
+        Ok(v) => {
+            eprintln!("chip8: recording video to {}", path.display());
+            Some(v)
+        }
+        Err(e) => {
+            eprintln!("chip8: could not record video: {}", e);
+            None
+        }
     }
 }
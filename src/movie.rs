@@ -0,0 +1,389 @@
+use std::fs;
+#[cfg(any(feature = "sdl", test))]
+use std::fs::File;
+#[cfg(any(feature = "sdl", test))]
+use std::io::{ self, BufWriter, Write };
+
+use crate::cpu::{ CPU, PROGRAM_START };
+use crate::display::{ CHIP8_HEIGHT, CHIP8_WIDTH };
+use crate::quirks::Quirks;
+use crate::timing::Ticks;
+
+// Movies are plain text so they can be attached to bug reports and diffed:
+//
+//   chip8-movie 1
+//   rom 9d9e1a3d1b2c8e40      hash of the loaded program
+//   seed 1234                 RNG seed for CXKK
+//   clock 540                 clock speed the movie was recorded at
+//   quirks shift clip         quirks that were on, named like the quirk.X settings
+//   load 200                  address the ROM was loaded at, in hex
+//   f 9 1 3:5+ 7:5-           cycles, timer ticks, then key changes as
+//   ...                       cycle:key followed by + (pressed) or - (released)
+//   end 5c1f0e0a7b23d4f1      hash of gfx after the last frame
+//
+// Every frame stores exactly how many cycles and timer ticks were run,
+// so playback doesn't depend on the speed of the machine playing it
+// Version 3 spreads timer ticks through the frame instead of running them at the end,
+// version 4 stores the quirks and load address
+pub const MOVIE_VERSION: u32 = 4;
+
+// A CHIP-8 key going down or up, as reported by the frontend
+// at is how far into the time since the last poll it happened, 0 to 1
+pub struct KeyEvent {
+    pub key: usize,
+    pub pressed: bool,
+    pub at: f64,
+}
+
+// The sound timer starting or stopping,
+// at is how far into the frame it happened, 0 to 1
+#[derive(Clone, Copy)]
+pub struct SoundEvent {
+    pub on: bool,
+    pub at: f64,
+}
+
+// A key event pinned to the cycle it's delivered before
+#[derive(Clone, Copy)]
+pub struct KeyChange {
+    pub cycle: u32,
+    pub key: usize,
+    pub pressed: bool,
+}
+
+// One pass of the main loop: the work that was run
+// and the key changes delivered in between cycles
+#[derive(Clone)]
+pub struct Frame {
+    pub ticks: Ticks,
+    pub changes: Vec<KeyChange>,
+}
+
+impl Frame {
+    // Spreads queued key events over the cycles of a frame
+    // Every event gets a cycle of its own, so a press and release that
+    // arrived together still leave the key down for at least one cycle.
+    // Events that don't fit stay queued for the next frame
+    pub fn new(ticks: Ticks, queued: &mut Vec<KeyEvent>) -> Frame {
+        let mut changes = Vec::new();
+        let mut next_free = 0;
+
+        for event in queued.iter() {
+            if next_free >= ticks.cycles {
+                break;
+            }
+
+            let wanted = (event.at * ticks.cycles as f64) as u32;
+            let cycle = wanted.max(next_free).min(ticks.cycles - 1);
+
+            changes.push(KeyChange { cycle, key: event.key, pressed: event.pressed });
+            next_free = cycle + 1;
+        }
+
+        queued.drain(..changes.len());
+
+        // Anything left over arrives at the very start of the next frame
+        for event in queued.iter_mut() {
+            event.at = 0.0;
+        }
+
+        Frame { ticks, changes }
+    }
+
+    // Runs the frame, keys holds the keypad state between frames
+    // Timer ticks are spread evenly between the cycles, the way they'd fall
+    // in real time, and every time the sound timer starts or stops that's
+    // returned with how far into the frame it happened
+    pub fn run(&self, cpu: &mut CPU, keys: &mut [bool; 16]) -> Vec<SoundEvent> {
+        let mut changes = self.changes.iter().peekable();
+        let mut sound_events = Vec::new();
+        let mut sounding = cpu.sound_timer > 0;
+        let (cycles, timers) = (self.ticks.cycles as u64, self.ticks.timers as u64);
+        let mut timers_done = 0;
+
+        for cycle in 0..cycles {
+            while let Some(change) = changes.next_if(|c| c.cycle as u64 == cycle) {
+                keys[change.key] = change.pressed;
+            }
+
+            cpu.emulate_cycle(*keys);
+
+            // Tick j is due once (j + 1) / timers of the frame has gone by
+            while timers_done < timers && (timers_done + 1) * cycles <= (cycle + 1) * timers {
+                cpu.decrement_timers();
+                timers_done += 1;
+            }
+
+            sound_change(cpu, &mut sounding, (cycle + 1) as f64 / cycles as f64, &mut sound_events);
+        }
+
+        // Only when no cycles ran at all
+        for _ in timers_done..timers {
+            cpu.decrement_timers();
+        }
+
+        sound_change(cpu, &mut sounding, 1.0, &mut sound_events);
+        sound_events
+    }
+}
+
+// Adds an event if the sound timer started or stopped
+fn sound_change(cpu: &CPU, sounding: &mut bool, at: f64, sound_events: &mut Vec<SoundEvent>) {
+    let on = cpu.sound_timer > 0;
+
+    if on != *sounding {
+        *sounding = on;
+        sound_events.push(SoundEvent { on, at });
+    }
+}
+
+pub struct Header {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub clock_hz: u32,
+    pub quirks: Quirks,
+    pub load_address: usize,
+}
+
+impl Header {
+    // Loads the ROM the way it was when the movie was recorded,
+    // whatever the current settings say
+    pub fn apply(&self, cpu: &mut CPU, rom: &str) -> Result<(), String> {
+        cpu.load_path(rom, self.load_address).map_err(|e| format!("{}: {}", rom, e))?;
+        cpu.quirks = self.quirks;
+        cpu.seed(self.seed);
+
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "sdl", test))]
+pub struct Recorder {
+    out: BufWriter<File>,
+}
+
+#[cfg(any(feature = "sdl", test))]
+impl Recorder {
+    pub fn create(path: &str, header: &Header) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+
+        writeln!(out, "chip8-movie {}", MOVIE_VERSION)?;
+        writeln!(out, "rom {:016x}", header.rom_hash)?;
+        writeln!(out, "seed {}", header.seed)?;
+        writeln!(out, "clock {}", header.clock_hz)?;
+        writeln!(out, "quirks {}", header.quirks.enabled().join(" "))?;
+        writeln!(out, "load {:X}", header.load_address)?;
+
+        Ok(Recorder { out })
+    }
+
+    pub fn frame(&mut self, frame: &Frame) -> io::Result<()> {
+        // Frames that ran nothing can't change anything
+        if frame.ticks.cycles == 0 && frame.ticks.timers == 0 {
+            return Ok(());
+        }
+
+        write!(self.out, "f {} {}", frame.ticks.cycles, frame.ticks.timers)?;
+
+        for change in &frame.changes {
+            let sign = if change.pressed { '+' } else { '-' };
+            write!(self.out, " {}:{:X}{}", change.cycle, change.key, sign)?;
+        }
+
+        writeln!(self.out)
+    }
+
+    // Writes the verification hash and flushes the file
+    pub fn finish(mut self, gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> io::Result<()> {
+        writeln!(self.out, "end {:016x}", hash_gfx(gfx))?;
+        self.out.flush()
+    }
+}
+
+pub struct Movie {
+    pub header: Header,
+    // Hash of gfx at the end, missing if the recording was cut short
+    pub gfx_hash: Option<u64>,
+    frames: Vec<Frame>,
+    position: usize,
+}
+
+impl Movie {
+    pub fn load(path: &str) -> Result<Movie, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read movie {}: {}", path, e))?;
+
+        let mut header = Header { rom_hash: 0, seed: 0, clock_hz: 0, quirks: Quirks::new(), load_address: PROGRAM_START };
+        let mut gfx_hash = None;
+        let mut frames = Vec::new();
+
+        for (n, line) in text.lines().enumerate() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bad_line = || format!("{}:{}: malformed movie line `{}`", path, n + 1, line);
+
+            match fields.as_slice() {
+                [] => {}
+                ["chip8-movie", version] => {
+                    if version.parse() != Ok(MOVIE_VERSION) {
+                        return Err(format!("{}: unsupported movie version {}", path, version));
+                    }
+                }
+                ["rom", hash] => header.rom_hash = u64::from_str_radix(hash, 16).map_err(|_| bad_line())?,
+                ["seed", seed] => header.seed = seed.parse().map_err(|_| bad_line())?,
+                ["clock", hz] => header.clock_hz = hz.parse().map_err(|_| bad_line())?,
+                ["quirks", names @ ..] => {
+                    header.quirks = Quirks { shift_vy: false, load_store_i: false, jump_vx: false, vf_reset: false, clip: false };
+
+                    for name in names {
+                        if !header.quirks.set(name, true) {
+                            return Err(bad_line());
+                        }
+                    }
+                }
+                ["load", address] => header.load_address = usize::from_str_radix(address, 16).map_err(|_| bad_line())?,
+                ["f", cycles, timers, changes @ ..] => {
+                    let ticks = Ticks {
+                        cycles: cycles.parse().map_err(|_| bad_line())?,
+                        timers: timers.parse().map_err(|_| bad_line())?,
+                    };
+
+                    let changes = changes.iter()
+                        .map(|c| parse_change(c).ok_or_else(bad_line))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    frames.push(Frame { ticks, changes });
+                }
+                ["end", hash] => gfx_hash = Some(u64::from_str_radix(hash, 16).map_err(|_| bad_line())?),
+                _ => return Err(bad_line()),
+            }
+        }
+
+        Ok(Movie {
+            header,
+            gfx_hash,
+            frames,
+            position: 0,
+        })
+    }
+
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        let frame = self.frames.get(self.position).cloned();
+
+        if frame.is_some() {
+            self.position += 1;
+        }
+
+        frame
+    }
+
+    // Whether playback ended on the same screen as the recording,
+    // None if the movie has no hash to check against
+    pub fn verify(&self, gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> Option<bool> {
+        self.gfx_hash.map(|hash| hash == hash_gfx(gfx))
+    }
+
+    // Back to the first frame
+    #[cfg(feature = "sdl")]
+    pub fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn finished(&self) -> bool {
+        self.position == self.frames.len()
+    }
+}
+
+// Parses a key change such as `7:5-`
+fn parse_change(text: &str) -> Option<KeyChange> {
+    let mut parts = text.splitn(2, ':');
+    let cycle = parts.next()?.parse().ok()?;
+    let rest = parts.next()?;
+
+    let pressed = match rest.chars().last()? {
+        '+' => true,
+        '-' => false,
+        _ => return None,
+    };
+
+    let key = usize::from_str_radix(&rest[..rest.len() - 1], 16).ok().filter(|&k| k < 16)?;
+
+    Some(KeyChange { cycle, key, pressed })
+}
+
+// 64-bit FNV-1a, small and stable across platforms and releases
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+pub fn hash_gfx(gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> u64 {
+    hash_bytes(&gfx.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn movies_read_back_what_was_recorded() {
+        let path = std::env::temp_dir().join(format!("chip8-movie-test-{}.c8m", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut quirks = Quirks::new();
+        quirks.set("clip", false);
+        quirks.set("shift", true);
+
+        let header = Header { rom_hash: 0x1234_abcd, seed: 42, clock_hz: 700, quirks, load_address: 0x600 };
+        let frames = [
+            Frame { ticks: Ticks { cycles: 12, timers: 1 }, changes: vec![KeyChange { cycle: 3, key: 0xF, pressed: true }] },
+            Frame { ticks: Ticks { cycles: 11, timers: 1 }, changes: vec![] },
+            Frame {
+                ticks: Ticks { cycles: 12, timers: 2 },
+                changes: vec![KeyChange { cycle: 0, key: 0xF, pressed: false }, KeyChange { cycle: 1, key: 0, pressed: true }],
+            },
+        ];
+
+        let mut gfx = [[0; CHIP8_WIDTH]; CHIP8_HEIGHT];
+        gfx[5][7] = 1;
+
+        let mut recorder = Recorder::create(path, &header).unwrap();
+
+        for frame in &frames {
+            recorder.frame(frame).unwrap();
+        }
+
+        recorder.finish(&gfx).unwrap();
+
+        let mut movie = Movie::load(path).unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(movie.header.rom_hash, header.rom_hash);
+        assert_eq!(movie.header.seed, 42);
+        assert_eq!(movie.header.clock_hz, 700);
+        assert!(movie.header.quirks == quirks);
+        assert_eq!(movie.header.load_address, 0x600);
+
+        for frame in &frames {
+            let read = movie.next_frame().unwrap();
+            let changes = |f: &Frame| f.changes.iter().map(|c| (c.cycle, c.key, c.pressed)).collect::<Vec<_>>();
+
+            assert_eq!((read.ticks.cycles, read.ticks.timers), (frame.ticks.cycles, frame.ticks.timers));
+            assert_eq!(changes(&read), changes(frame));
+        }
+
+        assert!(movie.next_frame().is_none());
+        assert_eq!(movie.verify(&gfx), Some(true));
+        assert_eq!(movie.verify(&[[0; CHIP8_WIDTH]; CHIP8_HEIGHT]), Some(false));
+    }
+
+    #[test]
+    fn key_changes_must_name_a_chip8_key() {
+        assert!(parse_change("7:F+").is_some());
+        assert!(parse_change("7:10+").is_none());
+        assert!(parse_change("7:G-").is_none());
+        assert!(parse_change("7:5").is_none());
+        assert!(parse_change("x:5+").is_none());
+    }
+}
@@ -0,0 +1,60 @@
+// Foreground and background colours as RGB
+// Kept free of SDL types so screenshots and recordings can use them too
+#[derive(Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+}
+
+pub const THEMES: [(&str, Palette); 5] = [
+    ("classic", Palette { fg: (0xFF, 0xFF, 0xFF), bg: (0x00, 0x00, 0x00) }),
+    ("amber", Palette { fg: (0xFF, 0xB0, 0x00), bg: (0x1A, 0x0F, 0x00) }),
+    ("green", Palette { fg: (0x33, 0xFF, 0x66), bg: (0x00, 0x14, 0x05) }),
+    ("lcd", Palette { fg: (0x0F, 0x38, 0x0F), bg: (0x9B, 0xBC, 0x0F) }),
+    ("high-contrast", Palette { fg: (0xFF, 0xFF, 0x00), bg: (0x00, 0x00, 0x00) }),
+];
+
+impl Palette {
+    pub fn new() -> Palette {
+        THEMES[0].1
+    }
+
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES.iter().find(|&&(n, _)| n == name).map(|&(_, p)| p)
+    }
+
+    // Colour of a gfx pixel
+    pub fn color(&self, pixel: u8) -> (u8, u8, u8) {
+        if pixel == 0 { self.bg } else { self.fg }
+    }
+
+    // Colour of a pixel that's partly lit, 0.0 is bg and 1.0 is fg
+    #[cfg(feature = "sdl")]
+    pub fn mix(&self, level: f32) -> (u8, u8, u8) {
+        let channel = |bg: u8, fg: u8| (bg as f32 + (fg as f32 - bg as f32) * level).round() as u8;
+
+        (
+            channel(self.bg.0, self.fg.0),
+            channel(self.bg.1, self.fg.1),
+            channel(self.bg.2, self.fg.2),
+        )
+    }
+}
+
+pub fn theme_names() -> Vec<&'static str> {
+    THEMES.iter().map(|&(n, _)| n).collect()
+}
+
+// Parses #RRGGBB, the # is optional
+pub fn parse_color(value: &str) -> Result<(u8, u8, u8), String> {
+    let hex = value.trim_start_matches('#');
+    let bad = || format!("colours are written as #RRGGBB, got `{}`", value);
+
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(bad());
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| bad());
+
+    Ok((channel(0)?, channel(2)?, channel(4)?))
+}
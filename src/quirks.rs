@@ -0,0 +1,73 @@
+// Opcodes that behave differently between CHIP-8 interpreters
+// Games were written against whichever interpreter their author had,
+// so each needs the behaviour it was tested with
+#[derive(Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8XY6 and 8XYE shift VY into VX instead of shifting VX in place
+    pub shift_vy: bool,
+    // FX55 and FX65 leave I pointing just past the last register
+    pub load_store_i: bool,
+    // BNNN jumps to NNN + VX, where X is the top nibble of NNN, instead of NNN + V0
+    pub jump_vx: bool,
+    // 8XY1, 8XY2 and 8XY3 clear VF
+    pub vf_reset: bool,
+    // Sprites are cut off at the edge of the screen instead of wrapping
+    pub clip: bool,
+}
+
+pub const QUIRK_PRESETS: [(&str, Quirks); 4] = [
+    // The original interpreter on the RCA COSMAC VIP
+    ("cosmac", Quirks { shift_vy: true, load_store_i: true, jump_vx: false, vf_reset: true, clip: true }),
+    // CHIP-48 on the HP-48 calculators, which most 90s games were made for
+    ("chip48", Quirks { shift_vy: false, load_store_i: false, jump_vx: true, vf_reset: false, clip: true }),
+    // SUPER-CHIP 1.1
+    ("schip", Quirks { shift_vy: false, load_store_i: false, jump_vx: true, vf_reset: false, clip: true }),
+    // What most emulators do, and what the bundled ROMs expect
+    ("modern", Quirks { shift_vy: false, load_store_i: false, jump_vx: false, vf_reset: false, clip: false }),
+];
+
+impl Quirks {
+    pub fn new() -> Quirks {
+        Quirks::preset("modern").unwrap()
+    }
+
+    pub fn preset(name: &str) -> Option<Quirks> {
+        QUIRK_PRESETS.iter()
+            .find(|&&(preset, _)| preset == name)
+            .map(|&(_, quirks)| quirks)
+    }
+
+    // Turns a quirk on or off by the name the quirk.X settings and
+    // movies use, false if there's no such quirk
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        let flag = match name {
+            "shift" => &mut self.shift_vy,
+            "load_store" => &mut self.load_store_i,
+            "jump" => &mut self.jump_vx,
+            "vf_reset" => &mut self.vf_reset,
+            "clip" => &mut self.clip,
+            _ => return false,
+        };
+
+        *flag = on;
+        true
+    }
+
+    // Names of the quirks that are on
+    #[cfg(any(feature = "sdl", test))]
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let flags = [
+            ("shift", self.shift_vy),
+            ("load_store", self.load_store_i),
+            ("jump", self.jump_vx),
+            ("vf_reset", self.vf_reset),
+            ("clip", self.clip),
+        ];
+
+        flags.iter().filter(|&&(_, on)| on).map(|&(name, _)| name).collect()
+    }
+}
+
+pub fn preset_names() -> Vec<&'static str> {
+    QUIRK_PRESETS.iter().map(|&(name, _)| name).collect()
+}
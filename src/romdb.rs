@@ -0,0 +1,112 @@
+// Settings for known ROMs, looked up by the SHA-1 of the ROM
+//
+// The database is a settings file split into sections, one per ROM:
+//
+//   [0123456789abcdef0123456789abcdef01234567]
+//   title = Pong
+//   platform = CHIP-8
+//   description = One player against the computer
+//   clock = 600
+//   quirks = cosmac
+//
+// title, platform and description describe the ROM, every other line is
+// a setting applied when the ROM is run. The database bundled with the
+// emulator is read first, then the user's, whose entries add to and
+// override the bundled ones
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+
+use crate::config::Config;
+
+const BUNDLED: &str = include_str!("romdb.cfg");
+
+// The user's database, unless CHIP8_ROMDB points somewhere else
+pub const USER_ROMDB_FILE: &str = "romdb.cfg";
+
+#[derive(Clone, Default)]
+pub struct Entry {
+    pub title: Option<String>,
+    pub platform: Option<String>,
+    pub description: Option<String>,
+    // key=value settings, in order
+    pub settings: Vec<String>,
+}
+
+pub struct RomDb {
+    entries: HashMap<String, Entry>,
+}
+
+impl RomDb {
+    pub fn empty() -> RomDb {
+        RomDb { entries: HashMap::new() }
+    }
+
+    // The bundled database and the user's, if there is one
+    pub fn load() -> Result<RomDb, String> {
+        let mut db = RomDb::empty();
+        db.parse(BUNDLED, "bundled romdb")?;
+
+        let user = env::var("CHIP8_ROMDB").unwrap_or_else(|_| USER_ROMDB_FILE.to_string());
+
+        if let Ok(text) = fs::read_to_string(&user) {
+            db.parse(&text, &user)?;
+        }
+
+        Ok(db)
+    }
+
+    fn parse(&mut self, text: &str, source: &str) -> Result<(), String> {
+        let mut current: Option<String> = None;
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = |e: String| format!("{}:{}: {}", source, n + 1, e);
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                let hash = line[1..line.len() - 1].trim().to_ascii_lowercase();
+
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(error(format!("`{}` isn't a SHA-1", hash)));
+                }
+
+                self.entries.entry(hash.clone()).or_default();
+                current = Some(hash);
+                continue;
+            }
+
+            let hash = current.as_ref()
+                .ok_or_else(|| error("settings before the first [sha1] section".to_string()))?;
+            let entry = self.entries.get_mut(hash).unwrap();
+
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().map(|v| v.trim().to_string());
+
+            match (key, value) {
+                ("title", Some(value)) => entry.title = Some(value),
+                ("platform", Some(value)) => entry.platform = Some(value),
+                ("description", Some(value)) => entry.description = Some(value),
+                _ => {
+                    // Checked now so mistakes point at the database, not the ROM
+                    Config::new().apply(line).map_err(error)?;
+                    entry.settings.push(line.to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&Entry> {
+        self.entries.get(&sha1(rom))
+    }
+}
+
+pub fn sha1(bytes: &[u8]) -> String {
+    sha1_smol::Sha1::from(bytes).digest().to_string()
+}
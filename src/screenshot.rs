@@ -0,0 +1,107 @@
+#[cfg(feature = "sdl")]
+use std::fs::File;
+#[cfg(feature = "sdl")]
+use std::io::{ self, BufWriter };
+#[cfg(feature = "sdl")]
+use std::path::{ Path, PathBuf };
+#[cfg(feature = "sdl")]
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use crate::display::{ CHIP8_HEIGHT, CHIP8_WIDTH };
+use crate::palette::Palette;
+
+// Colours gfx with the palette and blows every pixel up to scale x scale
+// Returns RGB bytes along with the width and height
+pub fn render(gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT], palette: &Palette, scale: usize) -> (Vec<u8>, usize, usize) {
+    let width = CHIP8_WIDTH * scale;
+    let height = CHIP8_HEIGHT * scale;
+    let mut rgb = Vec::with_capacity(width * height * 3);
+
+    for row in gfx.iter() {
+        for _ in 0..scale {
+            for &pixel in row.iter() {
+                let (r, g, b) = palette.color(pixel);
+
+                for _ in 0..scale {
+                    rgb.extend_from_slice(&[r, g, b]);
+                }
+            }
+        }
+    }
+
+    (rgb, width, height)
+}
+
+#[cfg(feature = "sdl")]
+pub fn save_png(path: &Path, rgb: &[u8], width: usize, height: usize) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(rgb).map_err(io::Error::other)
+}
+
+// Saves the screen as <dir>/<ROM>-<YYYYMMDD>-<HHMMSS>.png
+// scale 1 is the native 64x32
+#[cfg(feature = "sdl")]
+pub fn save(gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT], palette: &Palette, scale: usize, dir: &str, rom: &str) -> io::Result<PathBuf> {
+    let (rgb, width, height) = render(gfx, palette, scale);
+    let path = unique_path(dir, rom, "png");
+
+    save_png(&path, &rgb, width, height)?;
+    Ok(path)
+}
+
+// Picks a timestamped file name for the ROM that doesn't exist yet
+#[cfg(feature = "sdl")]
+pub fn unique_path(dir: &str, rom: &str, ext: &str) -> PathBuf {
+    let name = Path::new(rom)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "chip8".to_string());
+
+    let base = format!("{}-{}", name, timestamp());
+    let mut path = Path::new(dir).join(format!("{}.{}", base, ext));
+    let mut n = 2;
+
+    while path.exists() {
+        path = Path::new(dir).join(format!("{}-{}.{}", base, n, ext));
+        n += 1;
+    }
+
+    path
+}
+
+// Current UTC time as YYYYMMDD-HHMMSS
+#[cfg(feature = "sdl")]
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+    let (year, month, day) = civil_from_days(days);
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+// Days since 1970-01-01 to a calendar date, from Howard Hinnant's date algorithms
+#[cfg(feature = "sdl")]
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
@@ -0,0 +1,250 @@
+// Plays in a terminal, for machines without a window (e.g. over SSH)
+// Two pixels are drawn per character cell with half blocks,
+// keys come from the terminal in raw mode and sound is the terminal bell
+use std::io::{ self, Write };
+use std::time::{ Duration, Instant };
+
+use crossterm::{ cursor, execute, queue, terminal };
+use crossterm::event::{ self, Event, KeyCode, KeyEvent as TermKey, KeyEventKind, KeyModifiers };
+use crossterm::event::{ KeyboardEnhancementFlags, PushKeyboardEnhancementFlags, PopKeyboardEnhancementFlags };
+use crossterm::style::{ Color, Print, SetColors, Colors, ResetColor };
+
+use crate::config::Config;
+use crate::cpu::CPU;
+use crate::display::{ CHIP8_WIDTH, CHIP8_HEIGHT };
+use crate::movie::{ Frame, Header, KeyEvent };
+use crate::timing::{ Clock, FrameScheduler };
+use crate::watch::{ self, Watch, Watcher };
+
+// The COSMAC VIP keypad on the left of a QWERTY keyboard, in the order
+// 1 2 3 C / 4 5 6 D / 7 8 9 E / A 0 B F
+const KEYS: [(char, usize); 16] = [
+    ('1', 0x1), ('2', 0x2), ('3', 0x3), ('4', 0xC),
+    ('q', 0x4), ('w', 0x5), ('e', 0x6), ('r', 0xD),
+    ('a', 0x7), ('s', 0x8), ('d', 0x9), ('f', 0xE),
+    ('z', 0xA), ('x', 0x0), ('c', 0xB), ('v', 0xF),
+];
+
+// Most terminals only report presses, repeating while a key is held.
+// Without release events a key counts as held until it stops repeating
+const HOLD_TIME: Duration = Duration::from_millis(150);
+
+// Puts the terminal back however the frontend exits
+struct Guard {
+    enhanced: bool,
+}
+
+impl Guard {
+    fn enter() -> io::Result<Guard> {
+        terminal::enable_raw_mode()?;
+
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+
+        // Terminals with the kitty keyboard protocol report releases too
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+
+        if enhanced {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+
+        Ok(Guard { enhanced })
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+
+        if self.enhanced {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+
+        let _ = execute!(out, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Keyboard {
+    enhanced: bool,
+    // When each key was last seen down, for terminals without releases
+    last_seen: [Option<Instant>; 16],
+    state: [bool; 16],
+}
+
+impl Keyboard {
+    // Returns every CHIP-8 key press and release since the last poll,
+    // or Err when the player quit
+    fn poll(&mut self) -> io::Result<Result<Vec<KeyEvent>, ()>> {
+        let mut key_events = Vec::new();
+        let now = Instant::now();
+
+        while event::poll(Duration::from_secs(0))? {
+            let key = match event::read()? {
+                Event::Key(key) => key,
+                _ => continue,
+            };
+
+            if quits(&key) {
+                return Ok(Err(()));
+            }
+
+            let index = match key.code {
+                KeyCode::Char(c) => KEYS.iter()
+                    .find(|&&(k, _)| k == c.to_ascii_lowercase())
+                    .map(|&(_, index)| index),
+                _ => None,
+            };
+
+            let index = match index {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let down = key.kind != KeyEventKind::Release;
+
+            if !self.enhanced {
+                self.last_seen[index] = Some(now);
+            }
+
+            // Terminals don't timestamp their input, so everything
+            // lands at the end of the frame
+            self.set(index, down, &mut key_events);
+        }
+
+        if !self.enhanced {
+            for index in 0..16 {
+                let expired = self.last_seen[index].is_some_and(|t| now - t >= HOLD_TIME);
+
+                if expired {
+                    self.last_seen[index] = None;
+                    self.set(index, false, &mut key_events);
+                }
+            }
+        }
+
+        Ok(Ok(key_events))
+    }
+
+    fn set(&mut self, key: usize, pressed: bool, key_events: &mut Vec<KeyEvent>) {
+        if self.state[key] != pressed {
+            self.state[key] = pressed;
+            key_events.push(KeyEvent { key, pressed, at: 1.0 });
+        }
+    }
+}
+
+fn quits(key: &TermKey) -> bool {
+    key.code == KeyCode::Esc ||
+        (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+pub fn run(rom: &str, config: &Config) -> Result<(), String> {
+    // Nothing is recorded here, the header is only what the ROM is loaded with
+    let header = Header {
+        rom_hash: 0,
+        seed: config.seed.unwrap_or_else(rand::random),
+        clock_hz: config.clock_hz,
+        quirks: config.quirks,
+        load_address: config.load_address,
+    };
+
+    let mut cpu = CPU::new();
+    header.apply(&mut cpu, rom)?;
+
+    play(cpu, rom, &header, config).map_err(|e| format!("terminal error: {}", e))
+}
+
+fn play(mut cpu: CPU, rom: &str, header: &Header, config: &Config) -> io::Result<()> {
+    let guard = Guard::enter()?;
+    let mut keyboard = Keyboard {
+        enhanced: guard.enhanced,
+        last_seen: [None; 16],
+        state: [false; 16],
+    };
+
+    let mut clock = Clock::new(config.clock_hz);
+    let mut frames = FrameScheduler::new(false);
+    let mut keys = [false; 16];
+    let mut queued = Vec::new();
+
+    let mut watcher = if config.watch != Watch::Off { Some(Watcher::new(rom)) } else { None };
+
+    // Every frame since the game started, to replay when the ROM is reloaded
+    let mut history = Vec::new();
+
+    draw(&cpu.gfx, config)?;
+
+    while let Ok(events) = keyboard.poll()? {
+        queued.extend(events);
+
+        // The result goes on the line under the screen, printing it
+        // normally would scroll the screen in raw mode
+        if watcher.as_mut().is_some_and(|w| w.changed()) {
+            if config.watch == Watch::Restart {
+                history.clear();
+            }
+
+            let status = match watch::reload(rom, header, &history, &mut keys) {
+                Ok(reloaded) => {
+                    cpu = reloaded;
+                    watch::resync(&keys, &keyboard.state, &mut queued);
+                    format!("reloaded {}", rom)
+                }
+                Err(e) => format!("could not reload: {}", e),
+            };
+
+            let below = (CHIP8_HEIGHT / 2) as u16;
+            execute!(io::stdout(), ResetColor, cursor::MoveTo(0, below), terminal::Clear(terminal::ClearType::CurrentLine), Print(status))?;
+        }
+
+        let frame = Frame::new(clock.advance(1.0), &mut queued);
+        let sound_events = frame.run(&mut cpu, &mut keys);
+
+        if config.watch == Watch::Replay {
+            history.push(frame);
+        }
+
+        if cpu.draw_flag {
+            draw(&cpu.gfx, config)?;
+            cpu.draw_flag = false;
+        }
+
+        // The bell can't be held, so it rings once as each sound starts
+        if sound_events.iter().any(|e| e.on) && config.bell && !config.mute {
+            let mut out = io::stdout();
+            out.write_all(b"\x07")?;
+            out.flush()?;
+        }
+
+        frames.wait();
+    }
+
+    Ok(())
+}
+
+// Draws the screen as 64x16 character cells, the top pixel of each
+// pair in the cell's foreground and the bottom one in its background
+fn draw(gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT], config: &Config) -> io::Result<()> {
+    let (fr, fg, fb) = config.palette.fg;
+    let (br, bg, bb) = config.palette.bg;
+    let colors = Colors::new(Color::Rgb { r: fr, g: fg, b: fb }, Color::Rgb { r: br, g: bg, b: bb });
+
+    let mut out = io::stdout();
+    queue!(out, SetColors(colors))?;
+
+    for (row, pair) in gfx.chunks(2).enumerate() {
+        let line: String = pair[0].iter().zip(pair[1].iter())
+            .map(|(&top, &bottom)| match (top != 0, bottom != 0) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            })
+            .collect();
+
+        queue!(out, cursor::MoveTo(0, row as u16), Print(line))?;
+    }
+
+    out.flush()
+}
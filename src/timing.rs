@@ -0,0 +1,168 @@
+use std::thread;
+use std::time::{ Duration, Instant };
+
+// The delay and sound timers always count down at 60hz
+pub const TIMER_HZ: u32 = 60;
+
+// Roughly what the old fixed loop ran at (9 cycles per 60hz tick)
+pub const DEFAULT_CLOCK_HZ: u32 = 540;
+
+// Frames are presented at exactly this rate
+pub const FRAME_HZ: f64 = 60.0;
+
+// Fast-forward multipliers the hotkeys step through,
+// UNTHROTTLED runs as fast as the host allows
+pub const UNTHROTTLED: u32 = 0;
+pub const FAST_FORWARD_STEPS: [u32; 5] = [2, 4, 8, 16, UNTHROTTLED];
+
+// thread::sleep can overshoot by a millisecond or so,
+// so we wake up this early and spin for the rest of the frame
+const SPIN_MARGIN: Duration = Duration::from_micros(1500);
+
+// Never try to catch up on more than this much time at once,
+// otherwise a stall (dragging the window, a breakpoint) turns into
+// thousands of cycles being run in a single burst
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+// Work that has come due since the last call to advance
+#[derive(Clone, Copy)]
+pub struct Ticks {
+    pub cycles: u32,
+    pub timers: u32,
+}
+
+// Accumulator clock
+// Real time is added to two accumulators, and every time one of them
+// holds a full period (1 / clock_hz or 1 / 60) a cycle or timer tick is due.
+// Leftover time carries over, so the rates don't drift no matter how long
+// rendering or polling takes between calls
+pub struct Clock {
+    cycle_period: Duration,
+    timer_period: Duration,
+    cycle_acc: Duration,
+    timer_acc: Duration,
+    last: Instant,
+}
+
+impl Clock {
+    pub fn new(clock_hz: u32) -> Clock {
+        Clock {
+            cycle_period: period(clock_hz),
+            timer_period: period(TIMER_HZ),
+            cycle_acc: Duration::from_secs(0),
+            timer_acc: Duration::from_secs(0),
+            last: Instant::now(),
+        }
+    }
+
+    // Adds the real time elapsed since the previous call, scaled by rate
+    // A rate of 2.0 runs the CPU and timers twice as fast, 0.0 is paused
+    pub fn advance(&mut self, rate: f64) -> Ticks {
+        let now = Instant::now();
+        let elapsed = (now - self.last).min(MAX_CATCH_UP);
+        self.last = now;
+
+        self.advance_by(elapsed.mul_f64(rate))
+    }
+
+    // Adds an explicit amount of emulated time
+    pub fn advance_by(&mut self, elapsed: Duration) -> Ticks {
+        self.cycle_acc += elapsed;
+        self.timer_acc += elapsed;
+
+        Ticks {
+            cycles: drain(&mut self.cycle_acc, self.cycle_period),
+            timers: drain(&mut self.timer_acc, self.timer_period),
+        }
+    }
+
+    // Emulated time that passes in one frame at normal speed
+    pub fn frame_time() -> Duration {
+        Duration::from_secs_f64(1.0 / FRAME_HZ)
+    }
+}
+
+fn period(hz: u32) -> Duration {
+    Duration::from_nanos(1_000_000_000 / hz.max(1) as u64)
+}
+
+// Takes as many whole periods out of the accumulator as it holds
+fn drain(acc: &mut Duration, period: Duration) -> u32 {
+    let count = (acc.as_nanos() / period.as_nanos()) as u32;
+    *acc -= period * count;
+    count
+}
+
+// Frame scheduler
+// Keeps an absolute deadline for the next frame instead of sleeping a fixed
+// amount, so the time spent emulating and drawing is compensated for and
+// the frame rate doesn't drift. With vsync the display's present() does the
+// waiting and the scheduler only keeps track of missed frames
+pub struct FrameScheduler {
+    period: Duration,
+    next: Instant,
+    vsync: bool,
+    dropped: u32,
+}
+
+impl FrameScheduler {
+    pub fn new(vsync: bool) -> FrameScheduler {
+        let period = Clock::frame_time();
+
+        FrameScheduler {
+            period,
+            next: Instant::now() + period,
+            vsync,
+            dropped: 0,
+        }
+    }
+
+    // Blocks until the next frame is due
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+
+        if now > self.next {
+            // We're late, count every whole frame we missed
+            let late = now - self.next;
+            let missed = (late.as_nanos() / self.period.as_nanos()) as u32;
+
+            if missed > 0 {
+                // Too far behind to catch up, start counting from now
+                self.dropped += missed;
+                self.next = now + self.period;
+            } else {
+                self.next += self.period;
+            }
+
+            return;
+        }
+
+        if !self.vsync {
+            let remaining = self.next - now;
+
+            if remaining > SPIN_MARGIN {
+                thread::sleep(remaining - SPIN_MARGIN);
+            }
+
+            while Instant::now() < self.next {
+                thread::yield_now();
+            }
+        }
+
+        self.next += self.period;
+    }
+
+    // Starts counting frames from now, e.g. after running unthrottled
+    #[cfg(feature = "sdl")]
+    pub fn reset(&mut self) {
+        self.next = Instant::now() + self.period;
+    }
+
+    // Returns the number of frames dropped since the last call
+    #[cfg(feature = "sdl")]
+    pub fn take_dropped(&mut self) -> u32 {
+        let dropped = self.dropped;
+        self.dropped = 0;
+        dropped
+    }
+}
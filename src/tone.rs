@@ -0,0 +1,105 @@
+// The beep, generated sample by sample
+// Kept apart from the audio device so the same samples can be
+// computed without one
+
+pub const SAMPLE_RATE: u32 = 44100;
+
+pub const DEFAULT_FREQUENCY: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+// Lowest and highest pitch we accept, in Hz
+pub const MIN_FREQUENCY: f32 = 20.0;
+pub const MAX_FREQUENCY: f32 = 20_000.0;
+
+// How long the tone takes to fade in and out, in seconds.
+// Starting or stopping a wave mid-cycle jumps the speaker and clicks
+const RAMP_TIME: f32 = 0.005;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+pub const WAVEFORMS: [(&str, Waveform); 4] = [
+    ("square", Waveform::Square),
+    ("triangle", Waveform::Triangle),
+    ("sine", Waveform::Sine),
+    ("noise", Waveform::Noise),
+];
+
+pub struct Tone {
+    waveform: Waveform,
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+    // Whether the tone should be sounding
+    gate: bool,
+    // Current loudness of the envelope, 0.0 to 1.0
+    level: f32,
+    // How far the envelope moves each sample
+    ramp_step: f32,
+    // Noise shift register, clocked once per cycle of the tone
+    lfsr: u16,
+}
+
+impl Tone {
+    pub fn new(waveform: Waveform, frequency: f32, volume: f32, sample_rate: u32) -> Tone {
+        Tone {
+            waveform,
+            phase_inc: frequency / sample_rate as f32,
+            phase: 0.0,
+            volume,
+            gate: false,
+            level: 0.0,
+            ramp_step: 1.0 / (RAMP_TIME * sample_rate as f32),
+            lfsr: 0xACE1,
+        }
+    }
+
+    // Starts or stops the tone, it fades rather than cutting off
+    pub fn set_gate(&mut self, on: bool) {
+        self.gate = on;
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let target = if self.gate { 1.0 } else { 0.0 };
+
+        if self.level < target {
+            self.level = (self.level + self.ramp_step).min(target);
+        } else if self.level > target {
+            self.level = (self.level - self.ramp_step).max(target);
+        }
+
+        // Silent waves don't advance, so every beep starts at the same phase
+        if self.level == 0.0 {
+            self.phase = 0.0;
+            return 0.0;
+        }
+
+        let sample = match self.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * 2.0 * std::f32::consts::PI).sin(),
+            Waveform::Noise => if self.lfsr & 1 != 0 { 1.0 } else { -1.0 },
+        };
+
+        self.phase += self.phase_inc;
+
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+
+            // 16-bit Galois LFSR
+            let bit = self.lfsr & 1;
+            self.lfsr >>= 1;
+
+            if bit != 0 {
+                self.lfsr ^= 0xB400;
+            }
+        }
+
+        sample * self.volume * self.level
+    }
+}
@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+
+use crate::display::{ CHIP8_HEIGHT, CHIP8_WIDTH };
+use crate::palette::Palette;
+use crate::screenshot;
+use crate::timing::FRAME_HZ;
+
+pub const DEFAULT_VIDEO_SCALE: usize = 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum VideoFormat {
+    // Animated GIF
+    Gif,
+    // YUV4MPEG2, understood by ffmpeg, x264 and most other encoders
+    Y4m,
+    // Bare RGB24 frames back to back, for `ffmpeg -f rawvideo`
+    Rgb,
+}
+
+impl VideoFormat {
+    pub fn from_name(name: &str) -> Option<VideoFormat> {
+        match name {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            "rgb" | "raw" => Some(VideoFormat::Rgb),
+            _ => None,
+        }
+    }
+
+    // Guesses the format from a file name, streams to stdout default to y4m
+    pub fn from_path(path: &str) -> VideoFormat {
+        let ext = path.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+
+        match VideoFormat::from_name(&ext) {
+            Some(format) => format,
+            None if path == "-" => VideoFormat::Y4m,
+            None => VideoFormat::Rgb,
+        }
+    }
+
+    #[cfg(feature = "sdl")]
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+            VideoFormat::Rgb => "rgb",
+        }
+    }
+}
+
+enum Sink {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        // GIFs can only hold a frame for whole centiseconds, so identical
+        // frames are merged and held until the picture changes
+        held: Option<(Vec<u8>, u64)>,
+    },
+    Stream(Box<dyn Write>),
+}
+
+// Records the screen once per 60hz frame
+pub struct VideoRecorder {
+    sink: Sink,
+    format: VideoFormat,
+    palette: Palette,
+    scale: usize,
+    frames: u64,
+}
+
+impl VideoRecorder {
+    // A path of - writes to stdout so the stream can be piped to an encoder
+    pub fn create(path: &str, format: VideoFormat, palette: Palette, scale: usize) -> io::Result<VideoRecorder> {
+        let width = CHIP8_WIDTH * scale;
+        let height = CHIP8_HEIGHT * scale;
+
+        let sink = match format {
+            VideoFormat::Gif => {
+                if path == "-" {
+                    return Err(io::Error::other("GIFs can't be streamed to stdout"));
+                }
+
+                let (bg, fg) = (palette.bg, palette.fg);
+                let colors = [bg.0, bg.1, bg.2, fg.0, fg.1, fg.2];
+
+                let file = BufWriter::new(File::create(path)?);
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &colors)
+                    .map_err(io::Error::other)?;
+                encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+                Sink::Gif { encoder, held: None }
+            }
+            VideoFormat::Y4m => {
+                let mut out = open_stream(path)?;
+                writeln!(out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, FRAME_HZ as u32)?;
+                Sink::Stream(out)
+            }
+            VideoFormat::Rgb => Sink::Stream(open_stream(path)?),
+        };
+
+        Ok(VideoRecorder {
+            sink,
+            format,
+            palette,
+            scale,
+            frames: 0,
+        })
+    }
+
+    pub fn frame(&mut self, gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT]) -> io::Result<()> {
+        let frame = self.frames;
+        self.frames += 1;
+
+        match self.sink {
+            Sink::Gif { ref mut encoder, ref mut held } => {
+                // Palette indices, 0 is bg and 1 is fg
+                let pixels = scale_pixels(gfx, self.scale);
+
+                match held {
+                    Some((ref p, _)) if *p == pixels => Ok(()),
+                    _ => {
+                        let previous = held.replace((pixels, frame));
+                        write_gif_frame(encoder, previous, frame, self.scale)
+                    }
+                }
+            }
+            Sink::Stream(ref mut out) => {
+                let (rgb, width, height) = screenshot::render(gfx, &self.palette, self.scale);
+
+                match self.format {
+                    VideoFormat::Y4m => {
+                        out.write_all(b"FRAME\n")?;
+                        out.write_all(&rgb_to_yuv444(&rgb, width * height))
+                    }
+                    _ => out.write_all(&rgb),
+                }
+            }
+        }
+    }
+
+    // Writes out anything still held and flushes
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.sink {
+            Sink::Gif { ref mut encoder, ref mut held } => {
+                write_gif_frame(encoder, held.take(), self.frames, self.scale)
+            }
+            Sink::Stream(ref mut out) => out.flush(),
+        }
+    }
+}
+
+fn open_stream(path: &str) -> io::Result<Box<dyn Write>> {
+    if path == "-" {
+        Ok(Box::new(BufWriter::new(io::stdout())))
+    } else {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+// Writes a held GIF frame that started at frame `start` and lasted until `end`
+// Delays are worked out from the absolute frame times, so the
+// 1.67cs a 60hz frame lasts doesn't drift (2, 2, 1, 2, 2, 1, ...)
+fn write_gif_frame(encoder: &mut gif::Encoder<BufWriter<File>>, held: Option<(Vec<u8>, u64)>, end: u64, scale: usize) -> io::Result<()> {
+    let (pixels, start) = match held {
+        Some(h) => h,
+        None => return Ok(()),
+    };
+
+    let centis = |frame: u64| (frame as f64 * 100.0 / FRAME_HZ).round() as u64;
+    let delay = (centis(end) - centis(start)).clamp(1, u16::MAX as u64) as u16;
+
+    let mut frame = gif::Frame::from_indexed_pixels(
+        (CHIP8_WIDTH * scale) as u16,
+        (CHIP8_HEIGHT * scale) as u16,
+        pixels,
+        None,
+    );
+    frame.delay = delay;
+
+    encoder.write_frame(&frame).map_err(io::Error::other)
+}
+
+fn scale_pixels(gfx: &[[u8; CHIP8_WIDTH]; CHIP8_HEIGHT], scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(CHIP8_WIDTH * CHIP8_HEIGHT * scale * scale);
+
+    for row in gfx.iter() {
+        for _ in 0..scale {
+            for &pixel in row.iter() {
+                for _ in 0..scale {
+                    pixels.push((pixel != 0) as u8);
+                }
+            }
+        }
+    }
+
+    pixels
+}
+
+// Planar Y, U, V with full chroma, BT.601 studio range
+fn rgb_to_yuv444(rgb: &[u8], count: usize) -> Vec<u8> {
+    let mut yuv = vec![0u8; count * 3];
+
+    for (i, px) in rgb.chunks(3).enumerate() {
+        let (r, g, b) = (px[0] as f32, px[1] as f32, px[2] as f32);
+
+        yuv[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+        yuv[count + i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+        yuv[count * 2 + i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+    }
+
+    yuv
+}
@@ -0,0 +1,94 @@
+// Reloads the ROM when it changes on disk, for working on a game while it runs
+// The file's modification time is polled, which works the same everywhere
+// and is cheap enough at this rate
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant, SystemTime };
+
+use crate::cpu::CPU;
+use crate::movie::{ Frame, Header, KeyEvent };
+
+// How often the ROM is checked
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Watch {
+    Off,
+    // Start the new ROM from scratch
+    Restart,
+    // Run the new ROM through every frame played so far with the same
+    // inputs, so it picks up where the old one was
+    Replay,
+}
+
+pub const WATCH_MODES: [(&str, Watch); 3] = [
+    ("off", Watch::Off),
+    ("restart", Watch::Restart),
+    ("replay", Watch::Replay),
+];
+
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl Watcher {
+    pub fn new(path: &str) -> Watcher {
+        let path = PathBuf::from(path);
+        let modified = modified(&path);
+
+        Watcher { path, modified, last_check: Instant::now() }
+    }
+
+    // Whether the file changed since the last time this said so
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < WATCH_INTERVAL {
+            return false;
+        }
+
+        self.last_check = Instant::now();
+
+        // A file that's gone is usually halfway through being rewritten,
+        // so it only counts as changed once it's back
+        match modified(&self.path) {
+            Some(time) if Some(time) != self.modified => {
+                self.modified = Some(time);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// Loads the ROM into a fresh CPU the way the header says and runs it through
+// the frames played so far, keys is reset and ends up as the keypad was after the last of them
+pub fn reload(rom: &str, header: &Header, history: &[Frame], keys: &mut [bool; 16]) -> Result<CPU, String> {
+    let mut cpu = CPU::new();
+
+    header.apply(&mut cpu, rom)?;
+
+    *keys = [false; 16];
+
+    for frame in history {
+        frame.run(&mut cpu, keys);
+    }
+
+    cpu.draw_flag = true;
+    Ok(cpu)
+}
+
+// Queues key events ahead of the rest that bring keys in line with the keys held now,
+// which after a reload can differ from how the replayed frames left them
+pub fn resync(keys: &[bool; 16], held: &[bool; 16], queued: &mut Vec<KeyEvent>) {
+    let changes: Vec<KeyEvent> = (0..16)
+        .filter(|&key| keys[key] != held[key])
+        .map(|key| KeyEvent { key, pressed: held[key], at: 0.0 })
+        .collect();
+
+    queued.splice(0..0, changes);
+}